@@ -0,0 +1,475 @@
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use bevy::{
+    input::mouse::MouseMotion, prelude::*, render::primitives::Aabb, transform::TransformSystem,
+};
+use petri_shared::{Admin, Aim, Player, NEAR_RELEVANCE_RADIUS, PLAYER_HEIGHT};
+
+use crate::{
+    chat_plugin::chat_is_closed,
+    controls_plugin::{gamepad_stick, Action, Stick},
+    player_model_plugin::PlayerPart,
+    plugin::{Eyes, Me, PetriState},
+    raycast::cast_ray,
+    settings_plugin::Settings,
+};
+
+/// Turns mouse and gamepad input into [`LookAngles`] and points the [`PlayerCamera`]
+/// with them, in whichever [`CameraMode`] is on
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraMode>()
+            .add_systems(OnEnter(PetriState::Scene), spawn_camera_status)
+            .add_systems(
+                Update,
+                (
+                    (look, apply_look_angles).chain(),
+                    (
+                        switch_camera_mode.run_if(chat_is_closed),
+                        keep_camera_mode_valid,
+                        fly.run_if(resource_equals(CameraMode::FreeFly).and_then(chat_is_closed)),
+                        update_camera_status,
+                    )
+                        .chain(),
+                )
+                    .run_if(in_state(PetriState::Scene)),
+            )
+            .add_systems(
+                PostUpdate,
+                place_camera
+                    .before(TransformSystem::TransformPropagate)
+                    .run_if(in_state(PetriState::Scene)),
+            )
+            .add_systems(OnExit(PetriState::Scene), clean_up_camera);
+    }
+}
+
+/// How the [`PlayerCamera`] follows the game, switched with [`Action::SwitchCamera`]
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// From the player's eyes
+    #[default]
+    FirstPerson,
+    /// Orbits the player from behind, moving in when the level is in the way
+    ThirdPerson,
+    /// Flies around the player, only for admins
+    FreeFly,
+    /// Orbits another player to spectate them
+    Follow(Entity),
+}
+
+/// Whether movement input goes to the player rather than to the camera or nowhere
+pub fn camera_moves_player(mode: Res<CameraMode>) -> bool {
+    matches!(*mode, CameraMode::FirstPerson | CameraMode::ThirdPerson)
+}
+
+/// The camera the local player sees the scene through.
+/// It's not part of the player's rig so it can leave it.
+#[derive(Component)]
+pub struct PlayerCamera;
+
+#[derive(Component)]
+struct CameraStatus;
+
+/// Just short of straight up or down, looking exactly along Y flips the view around
+const PITCH_LIMIT: f32 = FRAC_PI_2 - 0.01;
+
+/// Smoothed angles jump to the target once they're this close, in radians
+const SNAP_ANGLE: f32 = 0.0001;
+
+/// Radians per second with the stick all the way
+const GAMEPAD_AIM_SPEED: f32 = 3.0;
+
+/// How far behind the head the third person camera orbits
+const ORBIT_DISTANCE: f32 = 4.0;
+/// How far the camera keeps from the level, so the near plane doesn't clip into it
+const CAMERA_RADIUS: f32 = 0.2;
+/// Meters per second
+const FREE_FLY_SPEED: f32 = 8.0;
+
+/// Where the local player is looking, kept on [`Me`].
+/// The yaw turns the [`Body`], the pitch only tilts the [`Eyes`].
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct LookAngles {
+    /// Radians around Y, zero looks towards -Z
+    pub yaw: f32,
+    /// Radians above the horizon, from -[`PITCH_LIMIT`] to [`PITCH_LIMIT`]
+    pub pitch: f32,
+    /// What the input asked for, `yaw` and `pitch` follow it when the mouse is smoothed
+    target_yaw: f32,
+    target_pitch: f32,
+}
+
+impl Default for LookAngles {
+    /// Looking towards +Z, the way players spawn
+    fn default() -> Self {
+        Self {
+            yaw: PI,
+            pitch: 0.0,
+            target_yaw: PI,
+            target_pitch: 0.0,
+        }
+    }
+}
+
+impl LookAngles {
+    /// Angles of someone else looking along `direction`, without smoothing
+    pub fn looking(direction: Direction3d) -> Self {
+        let yaw = f32::atan2(-direction.x, -direction.z);
+        let pitch = direction
+            .y
+            .clamp(-1.0, 1.0)
+            .asin()
+            .clamp(-PITCH_LIMIT, PITCH_LIMIT);
+        Self {
+            yaw,
+            pitch,
+            target_yaw: yaw,
+            target_pitch: pitch,
+        }
+    }
+
+    pub fn body_rotation(&self) -> Quat {
+        Quat::from_rotation_y(self.yaw)
+    }
+
+    pub fn head_rotation(&self) -> Quat {
+        Quat::from_rotation_x(self.pitch)
+    }
+
+    /// Forward along the ground, not affected by the pitch
+    pub fn forward(&self) -> Vec3 {
+        self.body_rotation() * Vec3::NEG_Z
+    }
+
+    pub fn rotation(&self) -> Quat {
+        self.body_rotation() * self.head_rotation()
+    }
+
+    pub fn direction(&self) -> Direction3d {
+        // rotations keep the length of Vec3::NEG_Z
+        Direction3d::new_unchecked(self.rotation() * Vec3::NEG_Z)
+    }
+
+    /// Turns by `delta` radians, +X to the right and +Y up
+    fn turn(&mut self, delta: Vec2) {
+        self.target_yaw -= delta.x;
+        self.target_pitch = (self.target_pitch + delta.y).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+
+        // keep the yaw small, both move together so smoothing doesn't spin the long way around
+        let wraps = (self.target_yaw / TAU).round() * TAU;
+        self.target_yaw -= wraps;
+        self.yaw -= wraps;
+    }
+
+    /// Moves towards the target, `smoothing` is the part of the way left after 1/60 s
+    fn follow(&mut self, smoothing: f32, delta_seconds: f32) {
+        let t = 1.0 - smoothing.clamp(0.0, 0.9).powf(delta_seconds * 60.0);
+        self.yaw += (self.target_yaw - self.yaw) * t;
+        self.pitch += (self.target_pitch - self.pitch) * t;
+        // stop creeping up on the target, or the angles would change every frame
+        if (self.target_yaw - self.yaw).abs() < SNAP_ANGLE {
+            self.yaw = self.target_yaw;
+        }
+        if (self.target_pitch - self.pitch).abs() < SNAP_ANGLE {
+            self.pitch = self.target_pitch;
+        }
+    }
+}
+
+/// Turns the body and head of the local player's rig
+#[derive(Component)]
+pub struct Body;
+
+/// Reads the mouse, only while the cursor is grabbed, and the right stick
+fn look(
+    mut mouse_motion_events: EventReader<MouseMotion>,
+    mut angles: Query<&mut LookAngles>,
+    windows: Query<&Window>,
+    settings: Res<Settings>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    time: Res<Time>,
+) {
+    let mouse_delta = mouse_motion_events.read().map(|e| e.delta).sum::<Vec2>();
+    let Ok(mut angles) = angles.get_single_mut() else {
+        return;
+    };
+    let mouse_grabbed = !windows.single().cursor.visible;
+    let stick = gamepad_stick(&gamepads, &axes, Stick::Right);
+
+    let mut delta = stick * GAMEPAD_AIM_SPEED * time.delta_seconds();
+    if mouse_grabbed {
+        // the mouse moving down the screen looks down
+        delta += mouse_delta * Vec2::new(1.0, -1.0) * settings.mouse_sensitivity;
+    }
+    if settings.invert_y {
+        delta.y = -delta.y;
+    }
+    if delta != Vec2::ZERO {
+        angles.turn(delta);
+    }
+    // only mark the angles changed when they move, the server hears about every change
+    let mut followed = *angles;
+    followed.follow(settings.mouse_smoothing, time.delta_seconds());
+    angles.set_if_neq(followed);
+}
+
+/// Rotates the body and the eyes, and tells the server where we're aiming
+fn apply_look_angles(
+    angles: Query<&LookAngles, Changed<LookAngles>>,
+    mut body: Query<&mut Transform, (With<Body>, Without<Eyes>)>,
+    mut eyes: Query<&mut Transform, With<Eyes>>,
+    mut events: EventWriter<Aim>,
+) {
+    let (Ok(angles), Ok(mut body), Ok(mut eyes)) = (
+        angles.get_single(),
+        body.get_single_mut(),
+        eyes.get_single_mut(),
+    ) else {
+        return;
+    };
+    body.rotation = angles.body_rotation();
+    eyes.rotation = angles.head_rotation();
+    events.send(Aim(angles.direction()));
+}
+
+/// Goes through first and third person, the free camera for admins,
+/// and every other player
+fn switch_camera_mode(
+    actions: Res<ButtonInput<Action>>,
+    mut mode: ResMut<CameraMode>,
+    me: Query<Has<Admin>, With<Me>>,
+    others: Query<Entity, (With<Player>, Without<Me>)>,
+) {
+    if !actions.just_pressed(Action::SwitchCamera) {
+        return;
+    }
+    let Ok(admin) = me.get_single() else {
+        return;
+    };
+    let mut modes = vec![CameraMode::FirstPerson, CameraMode::ThirdPerson];
+    if admin {
+        modes.push(CameraMode::FreeFly);
+    }
+    let mut others: Vec<_> = others.iter().collect();
+    others.sort();
+    modes.extend(others.into_iter().map(CameraMode::Follow));
+    let next = modes
+        .iter()
+        .position(|m| *m == *mode)
+        .map_or(0, |i| (i + 1) % modes.len());
+    *mode = modes[next];
+}
+
+/// Leaves modes that aren't allowed anymore, like following a player who left
+fn keep_camera_mode_valid(
+    mut mode: ResMut<CameraMode>,
+    me: Query<Has<Admin>, With<Me>>,
+    others: Query<Entity, (With<Player>, Without<Me>)>,
+) {
+    let Ok(admin) = me.get_single() else {
+        return;
+    };
+    let valid = match *mode {
+        CameraMode::FreeFly if !admin => CameraMode::FirstPerson,
+        CameraMode::Follow(target) if others.get(target).is_err() => others
+            .iter()
+            .min()
+            .map_or(CameraMode::FirstPerson, CameraMode::Follow),
+        mode => mode,
+    };
+    mode.set_if_neq(valid);
+}
+
+/// Moves the free camera where it's looking.
+/// The server only sends what is around the player, so the camera stays close to them.
+fn fly(
+    actions: Res<ButtonInput<Action>>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    me: Query<(&Transform, &LookAngles), With<Me>>,
+    mut camera: Query<&mut Transform, (With<PlayerCamera>, Without<Me>)>,
+    time: Res<Time>,
+) {
+    let (Ok((me, angles)), Ok(mut camera)) = (me.get_single(), camera.get_single_mut()) else {
+        return;
+    };
+    static MOVEMENT_ACTIONS: &[(Action, Vec3)] = &[
+        (Action::MoveLeft, Vec3::NEG_X),
+        (Action::MoveRight, Vec3::X),
+        (Action::MoveForward, Vec3::NEG_Z),
+        (Action::MoveBack, Vec3::Z),
+    ];
+    let stick = gamepad_stick(&gamepads, &axes, Stick::Left);
+    let mut direction = Vec3::new(stick.x, 0.0, -stick.y);
+    for (action, dir) in MOVEMENT_ACTIONS {
+        if actions.pressed(*action) {
+            direction += *dir;
+        }
+    }
+    camera.translation +=
+        angles.rotation() * direction.clamp_length_max(1.0) * FREE_FLY_SPEED * time.delta_seconds();
+
+    let head = me.translation + Vec3::Y * PLAYER_HEIGHT;
+    camera.translation = head + (camera.translation - head).clamp_length_max(NEAR_RELEVANCE_RADIUS);
+}
+
+/// Runs after the players have been moved, so the camera doesn't lag a frame behind
+#[allow(clippy::type_complexity)]
+fn place_camera(
+    mode: Res<CameraMode>,
+    me: Query<(&Transform, &LookAngles), With<Me>>,
+    others: Query<&Transform, (With<Player>, Without<Me>, Without<PlayerCamera>)>,
+    mut camera: Query<&mut Transform, (With<PlayerCamera>, Without<Me>, Without<Player>)>,
+    level: Query<(&Handle<Mesh>, &GlobalTransform, &Aabb), Without<PlayerPart>>,
+    meshes: Res<Assets<Mesh>>,
+) {
+    let (Ok((me, angles)), Ok(mut camera)) = (me.get_single(), camera.get_single_mut()) else {
+        return;
+    };
+    let head = |body: &Transform| body.translation + Vec3::Y * PLAYER_HEIGHT;
+    let orbit = |head: Vec3| {
+        let back = angles.rotation() * Vec3::Z;
+        let distance = cast_ray(Ray3d::new(head, back), ORBIT_DISTANCE, &meshes, &level)
+            .map_or(ORBIT_DISTANCE, |hit| (hit - CAMERA_RADIUS).max(0.0));
+        head + back * distance
+    };
+
+    camera.rotation = angles.rotation();
+    match *mode {
+        CameraMode::FirstPerson => camera.translation = head(me),
+        CameraMode::ThirdPerson => camera.translation = orbit(head(me)),
+        CameraMode::FreeFly => {}
+        CameraMode::Follow(target) => {
+            camera.translation = match others.get(target) {
+                Ok(target) => orbit(head(target)),
+                // gone, the mode is fixed up next frame
+                Err(_) => head(me),
+            }
+        }
+    }
+}
+
+fn spawn_camera_status(mut cmd: Commands, asset_server: Res<AssetServer>) {
+    cmd.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("open-sans.ttf"),
+                font_size: 24.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            align_self: AlignSelf::Center,
+            justify_self: JustifySelf::Center,
+            top: Val::Px(40.0),
+            ..default()
+        }),
+        CameraStatus,
+    ));
+}
+
+/// Says who is being spectated
+fn update_camera_status(
+    mode: Res<CameraMode>,
+    names: Query<&Name>,
+    mut status: Query<&mut Text, With<CameraStatus>>,
+) {
+    let Ok(mut status) = status.get_single_mut() else {
+        return;
+    };
+    let text = match *mode {
+        CameraMode::FreeFly => "Free camera".to_owned(),
+        CameraMode::Follow(target) => {
+            let name = names.get(target).map_or("someone", |n| n.as_str());
+            format!("Spectating {name}")
+        }
+        CameraMode::FirstPerson | CameraMode::ThirdPerson => String::new(),
+    };
+    if status.sections[0].value != text {
+        status.sections[0].value = text;
+    }
+}
+
+fn clean_up_camera(
+    mut cmd: Commands,
+    mut mode: ResMut<CameraMode>,
+    status: Query<Entity, With<CameraStatus>>,
+) {
+    *mode = default();
+    status
+        .iter()
+        .for_each(|e| cmd.entity(e).despawn_recursive());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level() -> LookAngles {
+        LookAngles::looking(Direction3d::NEG_Z)
+    }
+
+    #[test]
+    fn pitch_stops_at_the_limit() {
+        let mut look = level();
+        look.turn(Vec2::new(0.0, 10.0));
+        look.follow(0.0, 1.0 / 60.0);
+        assert_eq!(look.pitch, PITCH_LIMIT);
+        look.turn(Vec2::new(0.0, -20.0));
+        look.follow(0.0, 1.0 / 60.0);
+        assert_eq!(look.pitch, -PITCH_LIMIT);
+        assert_eq!(LookAngles::looking(Direction3d::Y).pitch, PITCH_LIMIT);
+    }
+
+    #[test]
+    fn yaw_wraps_without_spinning_the_long_way() {
+        let mut look = level();
+        look.yaw = PI - 0.1;
+        look.target_yaw = look.yaw;
+        // turning left across the wrap point
+        look.turn(Vec2::new(-0.2, 0.0));
+        assert!(look.target_yaw.abs() <= PI);
+        let before = look.yaw;
+        look.follow(0.5, 1.0 / 60.0);
+        // halfway there, not most of a turn around the other way
+        assert!(
+            (look.yaw - before - 0.1).abs() < 1e-4,
+            "{} {}",
+            before,
+            look.yaw
+        );
+
+        // spinning around stays within one turn
+        for _ in 0..1000 {
+            look.turn(Vec2::new(1.0, 0.0));
+            look.follow(0.5, 1.0 / 60.0);
+        }
+        assert!(look.target_yaw.abs() <= PI);
+        assert!(look.yaw.abs() <= TAU);
+    }
+
+    #[test]
+    fn smoothing_snaps_to_the_target() {
+        let mut look = level();
+        look.turn(Vec2::new(0.5, 0.3));
+        look.follow(0.5, 1.0 / 60.0);
+        assert_ne!(look.yaw, look.target_yaw);
+        for _ in 0..100 {
+            look.follow(0.5, 1.0 / 60.0);
+        }
+        assert_eq!(look.yaw, look.target_yaw);
+        assert_eq!(look.pitch, look.target_pitch);
+
+        // without smoothing it's there right away
+        look.turn(Vec2::new(0.5, 0.3));
+        look.follow(0.0, 1.0 / 60.0);
+        assert_eq!(look.yaw, look.target_yaw);
+        assert_eq!(look.pitch, look.target_pitch);
+    }
+}
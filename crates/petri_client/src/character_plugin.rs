@@ -0,0 +1,351 @@
+use std::{f32::consts::PI, path::Path, time::Duration};
+
+use bevy::{asset::LoadState, ecs::system::EntityCommands, gltf::Gltf, prelude::*};
+use petri_shared::{get_player_capsule_size, Motion, Player, ReplicatedAim};
+
+use crate::{
+    camera_plugin::LookAngles,
+    player_model_plugin::{spawn_player_model, PlayerModelAssets, PlayerPart},
+    plugin::PetriState,
+};
+
+/// Shows [`petri_shared::Appearance::Character`]s with their glTF model,
+/// animated to follow their [`Motion`]
+pub struct CharacterPlugin;
+
+impl Plugin for CharacterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                spawn_loaded_characters,
+                mark_character_parts,
+                turn_characters,
+                animate_characters,
+            )
+                .run_if(in_state(PetriState::Scene)),
+        );
+    }
+}
+
+/// How long one animation takes to blend into the next
+const BLEND_TIME: Duration = Duration::from_millis(250);
+
+/// Horizontal speeds in meters per second where walking and running start
+const WALK_SPEED: f32 = 0.3;
+const RUN_SPEED: f32 = 4.0;
+/// Horizontal speed the walk animation is made for, faster walks play it faster
+const WALK_ANIMATION_SPEED: f32 = 1.5;
+
+/// Waits for the model to load, then [`spawn_loaded_characters`] replaces it with a [`Character`]
+#[derive(Component)]
+struct PendingCharacter {
+    gltf: Handle<Gltf>,
+    /// For the shapes shown instead when the model can't be loaded
+    material: Handle<StandardMaterial>,
+}
+
+/// An entity shown with a glTF model
+#[derive(Component)]
+struct Character {
+    /// Root of the model's scene, turned where the character faces
+    body: Entity,
+    /// Indexed by [`Movement`]
+    clips: [Option<Handle<AnimationClip>>; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Movement {
+    Idle,
+    Walk,
+    Run,
+    Fall,
+}
+
+impl Movement {
+    const ALL: [Movement; 4] = [
+        Movement::Idle,
+        Movement::Walk,
+        Movement::Run,
+        Movement::Fall,
+    ];
+
+    fn from_motion(motion: &Motion) -> Self {
+        let speed = motion.velocity.xz().length();
+        if !motion.grounded {
+            Movement::Fall
+        } else if speed >= RUN_SPEED {
+            Movement::Run
+        } else if speed >= WALK_SPEED {
+            Movement::Walk
+        } else {
+            Movement::Idle
+        }
+    }
+
+    /// Name of the animation in the glTF file, in any case
+    fn clip_name(self) -> &'static str {
+        match self {
+            Movement::Idle => "Idle",
+            Movement::Walk => "Walk",
+            Movement::Run => "Run",
+            Movement::Fall => "Fall",
+        }
+    }
+
+    /// Playback speed, so feet keep up with the ground
+    fn playback_speed(self, motion: &Motion) -> f32 {
+        let reference_speed = match self {
+            Movement::Walk => WALK_ANIMATION_SPEED,
+            Movement::Run => RUN_SPEED,
+            Movement::Idle | Movement::Fall => return 1.0,
+        };
+        (motion.velocity.xz().length() / reference_speed).clamp(0.5, 2.0)
+    }
+}
+
+impl Character {
+    /// Characters without the clip for a movement stand idle instead
+    fn clip(&self, movement: Movement) -> Option<&Handle<AnimationClip>> {
+        self.clips[movement as usize]
+            .as_ref()
+            .or(self.clips[Movement::Idle as usize].as_ref())
+    }
+}
+
+/// Only models inside the assets folder, the path comes from the server.
+/// Asset paths use `/` on every platform, so Windows paths and asset sources are refused too.
+fn is_model_path(path: &str) -> bool {
+    if path.contains(['\\', ':']) {
+        return false;
+    }
+    let path = Path::new(path);
+    path.is_relative()
+        && path
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+        && path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("glb") || e.eq_ignore_ascii_case("gltf"))
+}
+
+/// Starts loading the model, shapes are shown until it's loaded
+pub fn spawn_character(
+    entity_builder: &mut EntityCommands,
+    asset_server: &AssetServer,
+    model: &str,
+    material: Handle<StandardMaterial>,
+) {
+    let gltf = if is_model_path(model) {
+        asset_server.load(model.to_owned())
+    } else {
+        warn!("Refusing to load character model {model:?}");
+        // never loads, so the shapes are shown right away
+        Handle::default()
+    };
+    entity_builder.insert((
+        SpatialBundle::default(),
+        PendingCharacter { gltf, material },
+    ));
+}
+
+/// Spawns the scenes of models that have loaded, and the shapes for those that failed
+fn spawn_loaded_characters(
+    mut commands: Commands,
+    pending: Query<(Entity, &PendingCharacter, Has<Player>)>,
+    gltfs: Res<Assets<Gltf>>,
+    asset_server: Res<AssetServer>,
+    player_model: Res<PlayerModelAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (entity, pending, is_player) in &pending {
+        let gltf = gltfs.get(&pending.gltf);
+        let scene = gltf.and_then(|g| g.default_scene.clone().or(g.scenes.first().cloned()));
+        let failed = asset_server.get_load_state(&pending.gltf) == Some(LoadState::Failed)
+            || pending.gltf == Handle::default()
+            || (gltf.is_some() && scene.is_none());
+        if gltf.is_none() && !failed {
+            continue;
+        }
+
+        let mut entity_builder = commands.entity(entity);
+        entity_builder.remove::<PendingCharacter>();
+        let (Some(gltf), Some(scene)) = (gltf, scene) else {
+            warn!("Showing shapes instead of {:?}", pending.gltf.path());
+            if is_player {
+                spawn_player_model(&mut entity_builder, &player_model, pending.material.clone());
+            } else {
+                let (capsule_diameter, capsule_segment_half_height) = get_player_capsule_size();
+                entity_builder.insert((
+                    meshes.add(Capsule3d::new(
+                        capsule_diameter / 2.0,
+                        capsule_segment_half_height * 2.0,
+                    )),
+                    pending.material.clone(),
+                ));
+            }
+            continue;
+        };
+
+        let (capsule_diameter, capsule_segment_half_height) = get_player_capsule_size();
+        let feet = capsule_segment_half_height + capsule_diameter / 2.0;
+        let body = entity_builder
+            .commands()
+            .spawn(SceneBundle {
+                scene,
+                transform: Transform::from_xyz(0.0, -feet, 0.0),
+                ..default()
+            })
+            .id();
+        let clips = Movement::ALL.map(|movement| {
+            gltf.named_animations
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(movement.clip_name()))
+                .map(|(_, clip)| clip.clone())
+        });
+        commands
+            .entity(entity)
+            .add_child(body)
+            .insert(Character { body, clips });
+    }
+}
+
+/// The camera sees through characters like through player models
+fn mark_character_parts(
+    mut commands: Commands,
+    meshes: Query<Entity, (Added<Handle<Mesh>>, Without<PlayerPart>)>,
+    parents: Query<&Parent>,
+    characters: Query<&Character>,
+) {
+    for mesh in &meshes {
+        if parents
+            .iter_ancestors(mesh)
+            .any(|ancestor| characters.contains(ancestor))
+        {
+            commands.entity(mesh).insert(PlayerPart);
+        }
+    }
+}
+
+/// Characters face where they aim, or else where they're going
+#[allow(clippy::type_complexity)]
+fn turn_characters(
+    // touching the body every frame would make bevy propagate its transform every frame
+    characters: Query<
+        (&Character, Option<&ReplicatedAim>, Option<&Motion>),
+        Or<(Added<Character>, Changed<ReplicatedAim>, Changed<Motion>)>,
+    >,
+    mut transforms: Query<&mut Transform>,
+) {
+    for (character, aim, motion) in &characters {
+        let yaw = match (aim, motion) {
+            (Some(aim), _) => LookAngles::looking(aim.0).yaw,
+            (None, Some(motion)) if motion.velocity.xz().length() >= WALK_SPEED => {
+                f32::atan2(-motion.velocity.x, -motion.velocity.z)
+            }
+            _ => continue,
+        };
+        if let Ok(mut body) = transforms.get_mut(character.body) {
+            // glTF models face +Z, we look along -Z
+            body.rotation = Quat::from_rotation_y(yaw + PI);
+        }
+    }
+}
+
+/// Blends into the animation of the current [`Movement`] once the scene has spawned
+fn animate_characters(
+    characters: Query<(&Character, &Motion)>,
+    children: Query<&Children>,
+    mut animation_players: Query<&mut AnimationPlayer>,
+) {
+    for (character, motion) in &characters {
+        let movement = Movement::from_motion(motion);
+        let Some(clip) = character.clip(movement) else {
+            continue;
+        };
+        let Some(mut animation_player) = children
+            .iter_descendants(character.body)
+            .find(|e| animation_players.contains(*e))
+            .and_then(|e| animation_players.get_mut(e).ok())
+        else {
+            continue;
+        };
+        if !animation_player.is_playing_clip(clip) {
+            animation_player
+                .play_with_transition(clip.clone(), BLEND_TIME)
+                .repeat();
+        }
+        let speed = movement.playback_speed(motion);
+        if animation_player.speed() != speed {
+            animation_player.set_speed(speed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moving(x: f32, y: f32, grounded: bool) -> Motion {
+        Motion {
+            velocity: Vec3::new(x, y, 0.0),
+            grounded,
+        }
+    }
+
+    #[test]
+    fn models_stay_in_the_assets_folder() {
+        assert!(is_model_path("characters/player.glb"));
+        assert!(is_model_path("monster.GLTF"));
+        assert!(!is_model_path("../player.glb"));
+        assert!(!is_model_path("characters/../../player.glb"));
+        assert!(!is_model_path("./player.glb"));
+        assert!(!is_model_path("/home/player.glb"));
+        assert!(!is_model_path(r"C:\player.glb"));
+        assert!(!is_model_path(r"characters\..\..\player.glb"));
+        assert!(!is_model_path("https://example.com/player.glb"));
+        assert!(!is_model_path("characters/player.obj"));
+        assert!(!is_model_path("characters/player"));
+        assert!(!is_model_path(""));
+    }
+
+    #[test]
+    fn movement_follows_horizontal_speed() {
+        assert_eq!(
+            Movement::from_motion(&moving(0.0, 0.0, true)),
+            Movement::Idle
+        );
+        assert_eq!(
+            Movement::from_motion(&moving(1.0, 0.0, true)),
+            Movement::Walk
+        );
+        assert_eq!(
+            Movement::from_motion(&moving(RUN_SPEED, 0.0, true)),
+            Movement::Run
+        );
+        // falling straight down or jumping forward
+        assert_eq!(
+            Movement::from_motion(&moving(0.0, -5.0, true)),
+            Movement::Idle
+        );
+        assert_eq!(
+            Movement::from_motion(&moving(5.0, 1.0, false)),
+            Movement::Fall
+        );
+    }
+
+    #[test]
+    fn playback_speed_keeps_up_with_the_ground() {
+        let walk = moving(WALK_ANIMATION_SPEED, 0.0, true);
+        assert_eq!(Movement::Walk.playback_speed(&walk), 1.0);
+        let run = moving(RUN_SPEED * 1.5, 0.0, true);
+        assert_eq!(Movement::Run.playback_speed(&run), 1.5);
+        // too slow or too fast looks wrong however the feet move
+        let crawl = moving(WALK_SPEED, 0.0, true);
+        assert_eq!(Movement::Walk.playback_speed(&crawl), 0.5);
+        let sprint = moving(RUN_SPEED * 10.0, 0.0, true);
+        assert_eq!(Movement::Run.playback_speed(&sprint), 2.0);
+        assert_eq!(Movement::Fall.playback_speed(&sprint), 1.0);
+        assert_eq!(Movement::Idle.playback_speed(&walk), 1.0);
+    }
+}
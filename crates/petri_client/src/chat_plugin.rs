@@ -0,0 +1,230 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    input::{keyboard::KeyboardInput, ButtonState},
+    prelude::*,
+};
+use petri_shared::{ChatBroadcast, ChatMessage, MAX_CHAT_MESSAGE_LEN};
+
+use crate::{controls_plugin::Action, plugin::PetriState};
+
+pub struct ChatPlugin;
+
+/// How many received messages are kept for scrolling back
+const CHAT_HISTORY_LEN: usize = 100;
+/// How many messages are visible in the chat box at once
+const CHAT_VISIBLE_LINES: usize = 8;
+const CHAT_FONT_SIZE: f32 = 16.0;
+
+/// The message the player is currently typing, if the chat input is open
+#[derive(Resource, Default, Debug)]
+pub struct ChatInput(Option<String>);
+
+#[derive(Resource, Default)]
+struct ChatHistory {
+    lines: VecDeque<ChatBroadcast>,
+    /// How many lines from the bottom the view is scrolled up
+    scroll: usize,
+}
+
+#[derive(Component)]
+struct ChatUIMarker;
+
+#[derive(Component)]
+struct ChatLog;
+
+#[derive(Component)]
+struct ChatInputLabel;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChatInput>()
+            .init_resource::<ChatHistory>()
+            .add_systems(OnEnter(PetriState::Scene), spawn_chat_box)
+            .add_systems(
+                Update,
+                (receive_chat, chat_input, scroll_chat, render_chat)
+                    .chain()
+                    .run_if(in_state(PetriState::Scene)),
+            )
+            .add_systems(OnExit(PetriState::Scene), despawn_chat_box);
+    }
+}
+
+/// Run condition for gameplay input that should be ignored while typing
+pub fn chat_is_closed(input: Res<ChatInput>) -> bool {
+    input.0.is_none()
+}
+
+fn spawn_chat_box(mut cmd: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("open-sans.ttf"),
+        font_size: CHAT_FONT_SIZE,
+        color: Color::WHITE,
+    };
+    cmd.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                width: Val::Px(500.0),
+                flex_direction: FlexDirection::Column,
+                ..default()
+            },
+            background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.3)),
+            ..default()
+        },
+        ChatUIMarker,
+    ))
+    .with_children(|parent| {
+        parent.spawn((TextBundle::from_sections([]), ChatLog));
+        parent.spawn((
+            TextBundle::from_section("", text_style).with_style(Style {
+                display: Display::None,
+                ..default()
+            }),
+            ChatInputLabel,
+        ));
+    });
+}
+
+fn despawn_chat_box(
+    mut cmd: Commands,
+    chat_box: Query<Entity, With<ChatUIMarker>>,
+    mut input: ResMut<ChatInput>,
+    mut history: ResMut<ChatHistory>,
+) {
+    chat_box
+        .iter()
+        .for_each(|e| cmd.entity(e).despawn_recursive());
+    *input = default();
+    *history = default();
+}
+
+fn receive_chat(mut events: EventReader<ChatBroadcast>, mut history: ResMut<ChatHistory>) {
+    for event in events.read() {
+        history.lines.push_back(event.clone());
+        if history.lines.len() > CHAT_HISTORY_LEN {
+            history.lines.pop_front();
+        }
+        // keep the view in place if the player is reading older messages
+        if history.scroll > 0 {
+            history.scroll += 1;
+        }
+    }
+}
+
+/// [`Action::Chat`] opens the chat input, Enter sends the message and Escape discards it
+fn chat_input(
+    mut char_input_events: EventReader<ReceivedCharacter>,
+    mut keyboard_input_events: EventReader<KeyboardInput>,
+    actions: Res<ButtonInput<Action>>,
+    mut input: ResMut<ChatInput>,
+    mut messages: EventWriter<ChatMessage>,
+) {
+    if input.0.is_none() {
+        // the key that opens the chat isn't typed into it
+        keyboard_input_events.clear();
+        char_input_events.clear();
+        if actions.just_pressed(Action::Chat) {
+            input.0 = Some(String::new());
+        }
+        return;
+    }
+
+    for event in keyboard_input_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match (event.key_code, &mut input.0) {
+            (KeyCode::Enter, Some(text)) => {
+                if !text.trim().is_empty() {
+                    messages.send(ChatMessage(std::mem::take(text)));
+                }
+                input.0 = None;
+            }
+            (KeyCode::Escape, Some(_)) => input.0 = None,
+            (KeyCode::Backspace, Some(text)) => {
+                text.pop();
+            }
+            _ => {}
+        }
+    }
+
+    let Some(text) = &mut input.0 else {
+        char_input_events.clear();
+        return;
+    };
+    for event in char_input_events.read() {
+        for c in event.char.chars().filter(|c| !c.is_control()) {
+            if text.chars().count() < MAX_CHAT_MESSAGE_LEN {
+                text.push(c);
+            }
+        }
+    }
+}
+
+/// PageUp and PageDown scroll through older messages
+fn scroll_chat(key: Res<ButtonInput<KeyCode>>, mut history: ResMut<ChatHistory>) {
+    let max_scroll = history.lines.len().saturating_sub(CHAT_VISIBLE_LINES);
+    if key.just_pressed(KeyCode::PageUp) {
+        history.scroll = (history.scroll + CHAT_VISIBLE_LINES).min(max_scroll);
+    }
+    if key.just_pressed(KeyCode::PageDown) {
+        history.scroll = history.scroll.saturating_sub(CHAT_VISIBLE_LINES);
+    }
+}
+
+fn render_chat(
+    history: Res<ChatHistory>,
+    input: Res<ChatInput>,
+    mut log: Query<&mut Text, (With<ChatLog>, Without<ChatInputLabel>)>,
+    mut input_label: Query<(&mut Text, &mut Style), With<ChatInputLabel>>,
+    asset_server: Res<AssetServer>,
+) {
+    if history.is_changed() {
+        let font = asset_server.load("open-sans.ttf");
+        let end = history.lines.len().saturating_sub(history.scroll);
+        let start = end.saturating_sub(CHAT_VISIBLE_LINES);
+        let sections: Vec<_> = history
+            .lines
+            .range(start..end)
+            .flat_map(|line| {
+                [
+                    TextSection::new(
+                        format!("{}: ", line.name),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: CHAT_FONT_SIZE,
+                            color: line.tint,
+                        },
+                    ),
+                    TextSection::new(
+                        format!("{}\n", line.text),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: CHAT_FONT_SIZE,
+                            color: Color::WHITE,
+                        },
+                    ),
+                ]
+            })
+            .collect();
+        for mut text in &mut log {
+            text.sections = sections.clone();
+        }
+    }
+
+    if input.is_changed() {
+        for (mut text, mut style) in &mut input_label {
+            match &input.0 {
+                Some(message) => {
+                    style.display = Display::Flex;
+                    text.sections[0].value = format!("> {message}_");
+                }
+                None => style.display = Display::None,
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Files the client keeps between runs, in the working directory by default
+
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use anyhow::Context;
+use bevy::{prelude::default, scene::ron};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// `default_path`, unless the environment variable `env_var` is set
+pub fn config_path(env_var: &str, default_path: &str) -> PathBuf {
+    std::env::var_os(env_var)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_path.into())
+}
+
+/// Returns `None` if the file doesn't exist yet
+pub fn load_config<T: DeserializeOwned>(path: &PathBuf) -> anyhow::Result<Option<T>> {
+    let text = match fs::read_to_string(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        result => result.with_context(|| format!("Could not read {}", path.display()))?,
+    };
+    ron::from_str::<T>(&text)
+        .map(Some)
+        .with_context(|| format!("Could not parse {}", path.display()))
+}
+
+pub fn save_config<T: Serialize>(path: &PathBuf, config: &T) -> anyhow::Result<()> {
+    let text = ron::ser::to_string_pretty(config, default())?;
+    fs::write(path, text).with_context(|| format!("Could not write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings_plugin::{Quality, Settings};
+
+    #[test]
+    fn saved_config_loads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.ron");
+        let settings = Settings {
+            invert_y: true,
+            fov_degrees: 70.0,
+            quality: Quality::High,
+            ..default()
+        };
+        save_config(&path, &settings).unwrap();
+        let loaded: Option<Settings> = load_config(&path).unwrap();
+        assert_eq!(loaded, Some(settings));
+    }
+
+    #[test]
+    fn missing_config_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let loaded: Option<Settings> = load_config(&dir.path().join("missing.ron")).unwrap();
+        assert_eq!(loaded, None);
+    }
+
+    #[test]
+    fn malformed_config_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.ron");
+        fs::write(&path, "(invert_y: maybe)").unwrap();
+        assert!(load_config::<Settings>(&path).is_err());
+    }
+
+    #[test]
+    fn config_from_another_version_keeps_known_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.ron");
+        // an older version had no `quality`, a newer one has `shadows`
+        fs::write(&path, "(invert_y: true, shadows: false)").unwrap();
+        let loaded: Option<Settings> = load_config(&path).unwrap();
+        assert_eq!(
+            loaded,
+            Some(Settings {
+                invert_y: true,
+                ..default()
+            })
+        );
+    }
+}
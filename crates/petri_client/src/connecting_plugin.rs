@@ -0,0 +1,356 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Duration, SystemTime},
+};
+
+use bevy::{asset::RecursiveDependencyLoadState, prelude::*};
+use bevy_replicon::{
+    prelude::*,
+    renet::{
+        transport::{ClientAuthentication, NetcodeClientTransport, NetcodeTransportError},
+        ConnectionConfig,
+    },
+};
+use petri_shared::protocol_user_data;
+
+use crate::{
+    disconnected_plugin::DisconnectReason,
+    plugin::{MyPlayerId, PetriState},
+    server_browser_plugin::{resolve, SelectedServer, DEFAULT_GAME_PORT},
+};
+
+/// Connects to the server while loading the assets of the scene,
+/// and enters the scene once both are done
+pub struct ConnectingPlugin;
+
+impl Plugin for ConnectingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ConnectionAttempt>()
+            .add_systems(
+                OnEnter(PetriState::Connecting),
+                (load_scene_assets, spawn_connecting_screen, start_connecting),
+            )
+            .add_systems(
+                Update,
+                (
+                    watch_connection,
+                    retry_or_leave,
+                    update_connecting_screen,
+                    enter_scene,
+                )
+                    .chain()
+                    .run_if(in_state(PetriState::Connecting)),
+            )
+            .add_systems(
+                OnExit(PetriState::Connecting),
+                |mut cmd: Commands, ui: Query<Entity, With<ConnectingUIMarker>>| {
+                    ui.iter().for_each(|e| cmd.entity(e).despawn_recursive());
+                },
+            );
+    }
+}
+
+/// Give up if the server hasn't accepted the connection by then
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Assets needed by the scene, kept here so they stay loaded between connections
+#[derive(Resource)]
+struct SceneAssets(Vec<(&'static str, UntypedHandle)>);
+
+#[derive(Resource, Default, Debug)]
+struct ConnectionAttempt {
+    server_addr: Option<SocketAddr>,
+    started_at: Duration,
+    /// Set when the attempt has failed, the player can retry then
+    error: Option<String>,
+}
+
+#[derive(Component)]
+struct ConnectingUIMarker;
+
+#[derive(Component)]
+struct ConnectionStatus;
+
+#[derive(Component)]
+struct AssetStatus;
+
+fn load_scene_assets(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    assets: Option<Res<SceneAssets>>,
+) {
+    if assets.is_some() {
+        return;
+    }
+    commands.insert_resource(SceneAssets(vec![
+        (
+            "level",
+            asset_server
+                .load::<Scene>("petrichor4-intro.glb#Scene0")
+                .untyped(),
+        ),
+        (
+            "skybox",
+            asset_server.load::<Image>("specular.ktx2").untyped(),
+        ),
+        (
+            "lighting",
+            asset_server.load::<Image>("diffuse.ktx2").untyped(),
+        ),
+        ("font", asset_server.load::<Font>("open-sans.ttf").untyped()),
+    ]));
+}
+
+fn spawn_connecting_screen(mut cmd: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("open-sans.ttf"),
+        font_size: 24.0,
+        ..default()
+    };
+    cmd.spawn((Camera2dBundle::default(), ConnectingUIMarker));
+    cmd.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(20.0),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::DARK_GRAY),
+            ..default()
+        },
+        ConnectingUIMarker,
+    ))
+    .with_children(|parent| {
+        parent.spawn((
+            TextBundle::from_section("", text_style.clone()).with_text_justify(JustifyText::Center),
+            ConnectionStatus,
+        ));
+        parent.spawn((
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font_size: 18.0,
+                    ..text_style
+                },
+            ),
+            AssetStatus,
+        ));
+    });
+}
+
+fn start_connecting(
+    mut commands: Commands,
+    network_channels: Res<NetworkChannels>,
+    mut attempt: ResMut<ConnectionAttempt>,
+    server: Option<Res<SelectedServer>>,
+    time: Res<Time>,
+) {
+    *attempt = ConnectionAttempt::start(
+        &mut commands,
+        &network_channels,
+        server.as_deref(),
+        time.elapsed(),
+    );
+}
+
+impl ConnectionAttempt {
+    fn start(
+        commands: &mut Commands,
+        network_channels: &NetworkChannels,
+        server: Option<&SelectedServer>,
+        now: Duration,
+    ) -> Self {
+        let mut attempt = Self {
+            started_at: now,
+            ..default()
+        };
+        let server = server.cloned().unwrap_or_else(|| SelectedServer {
+            host: Ipv4Addr::LOCALHOST.to_string(),
+            port: DEFAULT_GAME_PORT,
+        });
+        match connect(commands, network_channels, &server) {
+            Ok(server_addr) => attempt.server_addr = Some(server_addr),
+            Err(e) => {
+                error!("Could not connect: {e:?}");
+                attempt.error = Some(format!("{e:#}"));
+            }
+        }
+        attempt
+    }
+}
+
+fn connect(
+    commands: &mut Commands,
+    network_channels: &NetworkChannels,
+    server: &SelectedServer,
+) -> anyhow::Result<SocketAddr> {
+    let server_channels_config = network_channels.get_server_configs();
+    let client_channels_config = network_channels.get_client_configs();
+
+    let client = RenetClient::new(ConnectionConfig {
+        server_channels_config,
+        client_channels_config,
+        ..default()
+    });
+
+    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+    let client_id = current_time.as_millis() as u64;
+
+    let server_addr = SocketAddr::new(resolve(&server.host)?, server.port);
+    info!("Connecting to {server_addr:?}...");
+
+    let any_ip = match server_addr {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(any_ip, 0))?;
+    let authentication = ClientAuthentication::Unsecure {
+        client_id,
+        protocol_id: 0,
+        server_addr,
+        user_data: Some(protocol_user_data()),
+    };
+
+    let transport = NetcodeClientTransport::new(current_time, authentication, socket)?;
+
+    commands.insert_resource(MyPlayerId(client_id));
+    commands.insert_resource(client);
+    commands.insert_resource(transport);
+
+    Ok(server_addr)
+}
+
+/// Stops the connection attempt if it fails or takes too long
+fn watch_connection(
+    mut commands: Commands,
+    mut attempt: ResMut<ConnectionAttempt>,
+    mut transport_errors: EventReader<NetcodeTransportError>,
+    client: Option<Res<RenetClient>>,
+    transport: Option<Res<NetcodeClientTransport>>,
+    time: Res<Time>,
+) {
+    if attempt.error.is_some() {
+        transport_errors.clear();
+        return;
+    }
+    let Some(client) = client else {
+        return;
+    };
+
+    let error = if client.is_disconnected() {
+        Some(DisconnectReason::from_connection(&client, transport.as_deref()).to_string())
+    } else if let Some(e) = transport_errors.read().last() {
+        Some(e.to_string())
+    } else if !client.is_connected() && time.elapsed() - attempt.started_at > CONNECT_TIMEOUT {
+        Some(format!(
+            "The server did not respond in {} seconds",
+            CONNECT_TIMEOUT.as_secs()
+        ))
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        warn!("Could not connect: {error}");
+        attempt.error = Some(error);
+        commands.remove_resource::<NetcodeClientTransport>();
+        commands.remove_resource::<RenetClient>();
+    }
+}
+
+/// After a failed attempt, Enter tries again and Escape goes back to the server browser
+fn retry_or_leave(
+    mut commands: Commands,
+    key: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<PetriState>>,
+    mut attempt: ResMut<ConnectionAttempt>,
+    network_channels: Res<NetworkChannels>,
+    server: Option<Res<SelectedServer>>,
+    time: Res<Time>,
+) {
+    if key.just_pressed(KeyCode::Escape) {
+        commands.remove_resource::<NetcodeClientTransport>();
+        commands.remove_resource::<RenetClient>();
+        next_state.set(PetriState::ServerBrowser);
+    } else if attempt.error.is_some() && key.just_pressed(KeyCode::Enter) {
+        *attempt = ConnectionAttempt::start(
+            &mut commands,
+            &network_channels,
+            server.as_deref(),
+            time.elapsed(),
+        );
+    }
+}
+
+fn update_connecting_screen(
+    attempt: Res<ConnectionAttempt>,
+    client: Option<Res<RenetClient>>,
+    assets: Res<SceneAssets>,
+    asset_server: Res<AssetServer>,
+    mut status: Query<&mut Text, (With<ConnectionStatus>, Without<AssetStatus>)>,
+    mut asset_status: Query<&mut Text, With<AssetStatus>>,
+    time: Res<Time>,
+) {
+    let server = attempt
+        .server_addr
+        .map_or_else(|| "the server".to_owned(), |a| a.to_string());
+    let message = match (&attempt.error, client) {
+        (Some(error), _) => {
+            format!("Could not connect to {server}\n{error}\n\nEnter to retry, Escape to go back")
+        }
+        (None, Some(client)) if client.is_connected() => format!("Connected to {server}"),
+        (None, _) => format!(
+            "Connecting to {server}... {}s",
+            (time.elapsed() - attempt.started_at).as_secs()
+        ),
+    };
+    for mut text in &mut status {
+        if text.sections[0].value != message {
+            text.sections[0].value.clone_from(&message);
+        }
+    }
+
+    let states: Vec<_> = assets
+        .0
+        .iter()
+        .map(|(name, handle)| {
+            let state = asset_server.get_recursive_dependency_load_state(handle.id());
+            (name, state)
+        })
+        .collect();
+    let loaded = states
+        .iter()
+        .filter(|(_, s)| *s == Some(RecursiveDependencyLoadState::Loaded))
+        .count();
+    let mut message = format!("Loading assets: {loaded}/{}", states.len());
+    for (name, _) in states
+        .iter()
+        .filter(|(_, s)| *s == Some(RecursiveDependencyLoadState::Failed))
+    {
+        message.push_str(&format!("\nCould not load the {name}"));
+    }
+    for mut text in &mut asset_status {
+        if text.sections[0].value != message {
+            text.sections[0].value.clone_from(&message);
+        }
+    }
+}
+
+fn enter_scene(
+    client: Option<Res<RenetClient>>,
+    assets: Res<SceneAssets>,
+    asset_server: Res<AssetServer>,
+    mut next_state: ResMut<NextState<PetriState>>,
+) {
+    let connected = client.is_some_and(|c| c.is_connected());
+    let loaded = assets.0.iter().all(|(_, handle)| {
+        asset_server.get_recursive_dependency_load_state(handle.id())
+            == Some(RecursiveDependencyLoadState::Loaded)
+    });
+    if connected && loaded {
+        next_state.set(PetriState::Scene);
+    }
+}
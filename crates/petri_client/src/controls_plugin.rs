@@ -0,0 +1,415 @@
+//! Maps keys, mouse buttons and gamepad buttons to [`Action`]s, which the rest of the
+//! client reads from `ButtonInput<Action>`, and lets the player rebind them.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use bevy::{input::InputSystem, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{config_path, load_config, save_config},
+    plugin::PetriState,
+};
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        let bindings = Bindings::load().unwrap_or_else(|e| {
+            error!("{e:?}, using the default controls");
+            default()
+        });
+        app.insert_resource(bindings)
+            .init_resource::<ButtonInput<Action>>()
+            .init_resource::<ControlsScreen>()
+            .add_systems(PreUpdate, update_actions.after(InputSystem))
+            .add_systems(OnEnter(PetriState::Controls), spawn_controls_screen)
+            .add_systems(
+                Update,
+                (controls_input, update_controls_screen)
+                    .chain()
+                    .run_if(in_state(PetriState::Controls)),
+            )
+            .add_systems(
+                OnExit(PetriState::Controls),
+                (save_bindings, despawn_controls_screen),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    BuildWall,
+    UndoBuild,
+    Chat,
+    Scoreboard,
+    NetworkStats,
+    AimRays,
+    SwitchCamera,
+    GrabCursor,
+    ReleaseCursor,
+}
+
+impl Action {
+    const ALL: [Action; 13] = [
+        Action::MoveForward,
+        Action::MoveBack,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::BuildWall,
+        Action::UndoBuild,
+        Action::Chat,
+        Action::Scoreboard,
+        Action::NetworkStats,
+        Action::AimRays,
+        Action::SwitchCamera,
+        Action::GrabCursor,
+        Action::ReleaseCursor,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Action::MoveForward => "Move forward",
+            Action::MoveBack => "Move back",
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::BuildWall => "Build a wall",
+            Action::UndoBuild => "Undo building",
+            Action::Chat => "Chat",
+            Action::Scoreboard => "Scoreboard",
+            Action::NetworkStats => "Network stats",
+            Action::AimRays => "Show aim rays",
+            Action::SwitchCamera => "Switch camera",
+            Action::GrabCursor => "Grab the cursor",
+            Action::ReleaseCursor => "Release the cursor",
+        }
+    }
+}
+
+/// A key or a mouse button
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl std::fmt::Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{}", format!("{key:?}").trim_start_matches("Key")),
+            Binding::Mouse(button) => write!(f, "Mouse {button:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActionBindings {
+    pub binding: Option<Binding>,
+    pub gamepad: Option<GamepadButtonType>,
+}
+
+/// What triggers each [`Action`], movement and aim also come from the gamepad sticks
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings(BTreeMap<Action, ActionBindings>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Binding::{Key, Mouse};
+        let bind = |action, binding, gamepad| {
+            (
+                action,
+                ActionBindings {
+                    binding: Some(binding),
+                    gamepad,
+                },
+            )
+        };
+        Self(BTreeMap::from([
+            bind(Action::MoveForward, Key(KeyCode::KeyW), None),
+            bind(Action::MoveBack, Key(KeyCode::KeyS), None),
+            bind(Action::MoveLeft, Key(KeyCode::KeyA), None),
+            bind(Action::MoveRight, Key(KeyCode::KeyD), None),
+            bind(
+                Action::BuildWall,
+                Mouse(MouseButton::Right),
+                Some(GamepadButtonType::RightTrigger2),
+            ),
+            bind(
+                Action::UndoBuild,
+                Key(KeyCode::KeyZ),
+                Some(GamepadButtonType::West),
+            ),
+            bind(Action::Chat, Key(KeyCode::Enter), None),
+            bind(
+                Action::Scoreboard,
+                Key(KeyCode::Tab),
+                Some(GamepadButtonType::Select),
+            ),
+            bind(Action::NetworkStats, Key(KeyCode::F3), None),
+            bind(Action::AimRays, Key(KeyCode::F4), None),
+            bind(
+                Action::SwitchCamera,
+                Key(KeyCode::KeyV),
+                Some(GamepadButtonType::DPadUp),
+            ),
+            bind(Action::GrabCursor, Mouse(MouseButton::Left), None),
+            bind(Action::ReleaseCursor, Key(KeyCode::Escape), None),
+        ]))
+    }
+}
+
+fn bindings_path() -> PathBuf {
+    config_path("PETRI_BINDINGS", "bindings.ron")
+}
+
+impl Bindings {
+    fn load() -> anyhow::Result<Self> {
+        let mut bindings: Self = load_config(&bindings_path())?.unwrap_or_default();
+        // actions added since the file was saved get their default bindings
+        for (action, default) in Self::default().0 {
+            bindings.0.entry(action).or_insert(default);
+        }
+        Ok(bindings)
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        save_config(&bindings_path(), self)
+    }
+
+    pub fn get(&self, action: Action) -> ActionBindings {
+        self.0.get(&action).copied().unwrap_or_default()
+    }
+}
+
+/// Presses and releases actions like their bindings
+fn update_actions(
+    bindings: Res<Bindings>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut actions: ResMut<ButtonInput<Action>>,
+) {
+    actions.clear();
+    for action in Action::ALL {
+        let ActionBindings { binding, gamepad } = bindings.get(action);
+        let pressed = match binding {
+            Some(Binding::Key(key)) => keys.pressed(key),
+            Some(Binding::Mouse(button)) => mouse.pressed(button),
+            None => false,
+        } || gamepad.is_some_and(|button_type| {
+            gamepads
+                .iter()
+                .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(gamepad, button_type)))
+        });
+
+        if pressed && !actions.pressed(action) {
+            actions.press(action);
+        } else if !pressed && actions.pressed(action) {
+            actions.release(action);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Stick {
+    /// Moves the player
+    Left,
+    /// Aims
+    Right,
+}
+
+/// Sum of the sticks of all gamepads, X to the right and Y up
+pub fn gamepad_stick(gamepads: &Gamepads, axes: &Axis<GamepadAxis>, stick: Stick) -> Vec2 {
+    let (x, y) = match stick {
+        Stick::Left => (GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY),
+        Stick::Right => (GamepadAxisType::RightStickX, GamepadAxisType::RightStickY),
+    };
+    gamepads
+        .iter()
+        .map(|gamepad| {
+            Vec2::new(
+                axes.get(GamepadAxis::new(gamepad, x)).unwrap_or(0.0),
+                axes.get(GamepadAxis::new(gamepad, y)).unwrap_or(0.0),
+            )
+        })
+        .sum::<Vec2>()
+        .clamp_length_max(1.0)
+}
+
+/// Selected row of the controls screen, and whether it waits for a new binding
+#[derive(Resource, Default, Debug)]
+struct ControlsScreen {
+    selected: usize,
+    rebinding: bool,
+}
+
+#[derive(Component)]
+struct ControlsUIMarker;
+
+#[derive(Component)]
+struct ControlsText;
+
+fn spawn_controls_screen(mut cmd: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("open-sans.ttf"),
+        font_size: 26.0,
+        ..default()
+    };
+    cmd.spawn((Camera2dBundle::default(), ControlsUIMarker));
+    cmd.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(20.0),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::DARK_GRAY),
+            ..default()
+        },
+        ControlsUIMarker,
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section(
+            "Controls",
+            TextStyle {
+                font_size: 60.0,
+                ..text_style.clone()
+            },
+        ));
+        parent.spawn((
+            TextBundle::from_section("", text_style.clone()),
+            ControlsText,
+        ));
+        parent.spawn(TextBundle::from_section(
+            "Up/Down to select, Enter to rebind, Backspace to unbind, \
+             F5 to reset everything, Escape to go back",
+            TextStyle {
+                font_size: 18.0,
+                ..text_style
+            },
+        ));
+    });
+}
+
+fn despawn_controls_screen(mut cmd: Commands, ui: Query<Entity, With<ControlsUIMarker>>) {
+    ui.iter().for_each(|e| cmd.entity(e).despawn_recursive());
+}
+
+/// While rebinding, the next key, mouse button or gamepad button pressed is bound
+/// to the selected action. Keys and mouse buttons replace each other,
+/// gamepad buttons have their own slot.
+fn controls_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut screen: ResMut<ControlsScreen>,
+    mut bindings: ResMut<Bindings>,
+    mut next_state: ResMut<NextState<PetriState>>,
+) {
+    let action = Action::ALL[screen.selected];
+
+    if screen.rebinding {
+        if keys.just_pressed(KeyCode::Escape) {
+            screen.rebinding = false;
+            return;
+        }
+        let pressed = keys
+            .get_just_pressed()
+            .next()
+            .map(|key| Binding::Key(*key))
+            .or_else(|| mouse.get_just_pressed().next().map(|b| Binding::Mouse(*b)));
+        let gamepad = gamepad_buttons.get_just_pressed().next();
+        if pressed.is_none() && gamepad.is_none() {
+            return;
+        }
+
+        let action_bindings = bindings.0.entry(action).or_default();
+        if let Some(pressed) = pressed {
+            action_bindings.binding = Some(pressed);
+        }
+        if let Some(button) = gamepad {
+            action_bindings.gamepad = Some(button.button_type);
+        }
+        screen.rebinding = false;
+        return;
+    }
+
+    let count = Action::ALL.len();
+    if keys.just_pressed(KeyCode::ArrowUp) {
+        screen.selected = (screen.selected + count - 1) % count;
+    }
+    if keys.just_pressed(KeyCode::ArrowDown) {
+        screen.selected = (screen.selected + 1) % count;
+    }
+    if keys.just_pressed(KeyCode::Enter) {
+        screen.rebinding = true;
+    }
+    if keys.just_pressed(KeyCode::Backspace) {
+        bindings.0.insert(action, default());
+    }
+    if keys.just_pressed(KeyCode::F5) {
+        *bindings = default();
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        next_state.set(PetriState::MainMenu);
+    }
+}
+
+fn update_controls_screen(
+    screen: Res<ControlsScreen>,
+    bindings: Res<Bindings>,
+    mut text: Query<(&mut Text, Ref<ControlsText>)>,
+) {
+    let Ok((mut text, marker)) = text.get_single_mut() else {
+        return;
+    };
+    if !screen.is_changed() && !bindings.is_changed() && !marker.is_added() {
+        return;
+    }
+    let style = text.sections[0].style.clone();
+    text.sections = Action::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, action)| {
+            let ActionBindings { binding, gamepad } = bindings.get(*action);
+            let line = if i == screen.selected && screen.rebinding {
+                format!("{}: press a key or a button...\n", action.label())
+            } else {
+                format!(
+                    "{}: {}  /  {}\n",
+                    action.label(),
+                    binding.map_or_else(|| "-".to_owned(), |b| b.to_string()),
+                    gamepad.map_or_else(|| "-".to_owned(), |b| format!("Gamepad {b:?}")),
+                )
+            };
+            let color = if i == screen.selected {
+                Color::YELLOW
+            } else {
+                Color::WHITE
+            };
+            TextSection::new(
+                line,
+                TextStyle {
+                    color,
+                    ..style.clone()
+                },
+            )
+        })
+        .collect();
+}
+
+fn save_bindings(bindings: Res<Bindings>) {
+    if let Err(e) = bindings.save() {
+        error!("{e:?}");
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+
+use bevy::{prelude::*, window::CursorGrabMode};
+use bevy_replicon::{
+    prelude::*,
+    renet::{
+        transport::{NetcodeClientTransport, NetcodeDisconnectReason},
+        DisconnectReason as RenetDisconnectReason,
+    },
+};
+use petri_shared::{DisconnectNotice, PROTOCOL_VERSION};
+
+use crate::{network_hud_plugin::ShutdownCountdown, plugin::PetriState};
+
+/// Notices lost connections and shows why they were lost
+pub struct DisconnectedPlugin;
+
+impl Plugin for DisconnectedPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LastDisconnectNotice>()
+            .add_systems(
+                Update,
+                (receive_disconnect_notices, detect_disconnect)
+                    .chain()
+                    .run_if(in_state(PetriState::Scene)),
+            )
+            .add_systems(OnEnter(PetriState::Disconnected), disconnected_screen)
+            .add_systems(
+                Update,
+                disconnected_input.run_if(in_state(PetriState::Disconnected)),
+            )
+            .add_systems(
+                OnExit(PetriState::Disconnected),
+                |mut cmd: Commands, ui: Query<Entity, With<DisconnectedUIMarker>>| {
+                    ui.iter().for_each(|e| cmd.entity(e).despawn_recursive());
+                    cmd.remove_resource::<DisconnectReason>();
+                },
+            );
+    }
+}
+
+/// Why the client is no longer connected to the server
+#[derive(Resource, Debug)]
+pub enum DisconnectReason {
+    ServerShutdown,
+    Kicked(String),
+    VersionMismatch { server_version: u64 },
+    TimedOut,
+    ConnectionDenied,
+    DisconnectedByServer,
+    Error(String),
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ServerShutdown => write!(f, "The server has shut down"),
+            Self::Kicked(reason) => write!(f, "Kicked from the server: {reason}"),
+            Self::VersionMismatch { server_version } => write!(
+                f,
+                "The server runs a different version of the game \
+                (protocol {server_version}, yours is {PROTOCOL_VERSION})"
+            ),
+            Self::TimedOut => write!(f, "The connection has timed out"),
+            Self::ConnectionDenied => {
+                write!(f, "The server refused the connection, it may be full")
+            }
+            Self::DisconnectedByServer => write!(f, "Disconnected by the server"),
+            Self::Error(e) => write!(f, "Connection error: {e}"),
+        }
+    }
+}
+
+impl DisconnectReason {
+    /// Finds out why the connection was lost from renet and its transport
+    pub fn from_connection(
+        client: &RenetClient,
+        transport: Option<&NetcodeClientTransport>,
+    ) -> Self {
+        match (
+            transport.and_then(|t| t.disconnect_reason()),
+            client.disconnect_reason(),
+        ) {
+            (
+                Some(
+                    NetcodeDisconnectReason::ConnectionTimedOut
+                    | NetcodeDisconnectReason::ConnectionRequestTimedOut
+                    | NetcodeDisconnectReason::ConnectionResponseTimedOut,
+                ),
+                _,
+            ) => Self::TimedOut,
+            (Some(NetcodeDisconnectReason::ConnectionDenied), _) => Self::ConnectionDenied,
+            (Some(NetcodeDisconnectReason::DisconnectedByServer), _)
+            | (_, Some(RenetDisconnectReason::DisconnectedByServer)) => Self::DisconnectedByServer,
+            (Some(netcode), _) => Self::Error(format!("{netcode:?}")),
+            (None, Some(renet)) => Self::Error(format!("{renet:?}")),
+            (None, None) => Self::Error("unknown".to_owned()),
+        }
+    }
+}
+
+/// The server tells why it is about to disconnect us
+#[derive(Resource, Default, Debug)]
+struct LastDisconnectNotice(Option<DisconnectNotice>);
+
+#[derive(Component)]
+struct DisconnectedUIMarker;
+
+fn receive_disconnect_notices(
+    mut notices: EventReader<DisconnectNotice>,
+    mut last_notice: ResMut<LastDisconnectNotice>,
+) {
+    if let Some(notice) = notices.read().last() {
+        warn!("Server is disconnecting us: {notice:?}");
+        last_notice.0 = Some(notice.clone());
+    }
+}
+
+fn detect_disconnect(
+    mut commands: Commands,
+    client: Option<Res<RenetClient>>,
+    transport: Option<Res<NetcodeClientTransport>>,
+    mut last_notice: ResMut<LastDisconnectNotice>,
+    countdown: Res<ShutdownCountdown>,
+    mut next_state: ResMut<NextState<PetriState>>,
+) {
+    let Some(client) = client.filter(|c| c.is_disconnected()) else {
+        return;
+    };
+    let reason = match last_notice.0.take() {
+        Some(DisconnectNotice::Kicked(reason)) => DisconnectReason::Kicked(reason),
+        Some(DisconnectNotice::VersionMismatch { server_version }) => {
+            DisconnectReason::VersionMismatch { server_version }
+        }
+        None if countdown.0.is_some() => DisconnectReason::ServerShutdown,
+        None => DisconnectReason::from_connection(&client, transport.as_deref()),
+    };
+    warn!("Disconnected: {reason}");
+    commands.insert_resource(reason);
+    next_state.set(PetriState::Disconnected);
+}
+
+fn disconnected_screen(
+    mut cmd: Commands,
+    asset_server: Res<AssetServer>,
+    reason: Option<Res<DisconnectReason>>,
+    mut windows: Query<&mut Window>,
+) {
+    for mut window in &mut windows {
+        window.cursor.visible = true;
+        window.cursor.grab_mode = CursorGrabMode::None;
+    }
+
+    let font = asset_server.load("open-sans.ttf");
+    let reason = reason.map_or_else(|| "Disconnected".to_owned(), |r| r.to_string());
+    cmd.spawn((Camera2dBundle::default(), DisconnectedUIMarker));
+    cmd.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(20.0),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::DARK_GRAY),
+            ..default()
+        },
+        DisconnectedUIMarker,
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section(
+            "Disconnected",
+            TextStyle {
+                font: font.clone(),
+                font_size: 60.0,
+                ..default()
+            },
+        ));
+        parent.spawn(
+            TextBundle::from_section(
+                reason,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 24.0,
+                    color: Color::ORANGE_RED,
+                },
+            )
+            .with_text_justify(JustifyText::Center),
+        );
+        parent.spawn(TextBundle::from_section(
+            "Enter to reconnect, Escape to go back to login",
+            TextStyle {
+                font,
+                font_size: 18.0,
+                ..default()
+            },
+        ));
+    });
+}
+
+fn disconnected_input(
+    key: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<PetriState>>,
+) {
+    if key.just_pressed(KeyCode::Enter) {
+        next_state.set(PetriState::Connecting);
+    } else if key.just_pressed(KeyCode::Escape) {
+        next_state.set(PetriState::Login);
+    }
+}
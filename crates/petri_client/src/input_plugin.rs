@@ -0,0 +1,101 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use petri_shared::{
+    Aim, InputBatch, InputFrame, MoveDirection, INPUT_BATCHES_PER_SECOND, INPUT_REDUNDANCY,
+};
+
+use crate::plugin::PetriState;
+
+/// Collects [`MoveDirection`] and [`Aim`] of every frame and sends the latest of them
+/// to the server in redundant [`InputBatch`]es, [`INPUT_BATCHES_PER_SECOND`] at most
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<MoveDirection>()
+            .add_event::<Aim>()
+            .init_resource::<InputHistory>()
+            .add_systems(
+                PostUpdate,
+                send_input_batch
+                    .before(ClientSet::Send)
+                    .run_if(in_state(PetriState::Scene)),
+            )
+            .add_systems(OnExit(PetriState::Scene), reset_input_history);
+    }
+}
+
+#[derive(Resource)]
+struct InputHistory {
+    frames: VecDeque<InputFrame>,
+    last_aim: Direction3d,
+    /// Latest movement since the last batch was sent
+    pending_movement: Option<Vec2>,
+    /// Whether the aim changed since the last batch was sent
+    pending_aim: bool,
+    /// When the next batch may be sent
+    next_send: Duration,
+}
+
+impl Default for InputHistory {
+    fn default() -> Self {
+        Self {
+            frames: VecDeque::with_capacity(INPUT_REDUNDANCY),
+            last_aim: Direction3d::NEG_Z,
+            pending_movement: None,
+            pending_aim: false,
+            next_send: Duration::ZERO,
+        }
+    }
+}
+
+/// The server numbers the frames of every connection from scratch,
+/// old frames would be applied to the new player
+fn reset_input_history(mut history: ResMut<InputHistory>) {
+    *history = default();
+}
+
+const SEND_INTERVAL: Duration = Duration::from_micros((1e6 / INPUT_BATCHES_PER_SECOND) as u64);
+
+fn send_input_batch(
+    mut movement: EventReader<MoveDirection>,
+    mut aim: EventReader<Aim>,
+    mut history: ResMut<InputHistory>,
+    mut batches: EventWriter<InputBatch>,
+    time: Res<Time<Real>>,
+) {
+    // read every frame, events don't wait for the next batch
+    if let Some(movement) = movement.read().last() {
+        history.pending_movement = Some(movement.0);
+    }
+    if let Some(aim) = aim.read().last() {
+        history.last_aim = aim.0;
+        history.pending_aim = true;
+    }
+    let now = time.elapsed();
+    if now < history.next_send {
+        return;
+    }
+    let movement = history.pending_movement.take();
+    let aim_changed = std::mem::take(&mut history.pending_aim);
+    if movement.is_none() && !aim_changed {
+        return;
+    }
+    // after a long frame the next batch can go right away, but only one
+    history.next_send = (history.next_send + SEND_INTERVAL).max(now);
+
+    let sequence = history.frames.back().map_or(1, |f| f.sequence + 1);
+    let frame = InputFrame {
+        sequence,
+        movement,
+        aim: history.last_aim,
+    };
+    if history.frames.len() == INPUT_REDUNDANCY {
+        history.frames.pop_front();
+    }
+    history.frames.push_back(frame);
+
+    batches.send(InputBatch(history.frames.iter().cloned().collect()));
+}
@@ -2,24 +2,42 @@ use bevy::{
     app::{App, Plugin, Update},
     asset::AssetServer,
     hierarchy::DespawnRecursiveExt,
-    input::keyboard::KeyboardInput,
     prelude::{
-        default, in_state, AlignItems, BackgroundColor, BuildChildren, Camera2dBundle, Color,
-        Commands, Component, Entity, EventReader, FlexDirection, IntoSystemConfigs, JustifyContent,
-        JustifyText, KeyCode, NextState, NodeBundle, OnEnter, OnExit, Outline, Query,
-        ReceivedCharacter, Res, ResMut, Resource, Style, Text, TextBundle, TextStyle, Val, With,
+        default, in_state, AlignItems, BackgroundColor, BuildChildren, ButtonInput, Camera2dBundle,
+        ChildBuilder, Color, Commands, Component, Entity, FlexDirection, IntoSystemConfigs,
+        JustifyContent, JustifyText, KeyCode, NextState, NodeBundle, OnEnter, OnExit, Outline,
+        Query, Res, ResMut, Resource, Style, Text, TextBundle, TextStyle, UiRect, Val, With,
+        Without,
     },
 };
+use petri_shared::MAX_NAME_LEN;
 
-use crate::plugin::PetriState;
+use crate::{
+    plugin::PetriState,
+    server_browser_plugin::SelectedServer,
+    text_input_plugin::{TextInput, TextInputSet},
+};
 
 pub struct LoginPlugin;
 
 #[derive(Resource, Debug)]
 pub struct CurrentUserLogin(pub String);
 
+/// What was typed into the server field, kept for the next visit to the login screen
+#[derive(Resource, Debug)]
+struct LastServerAddress(String);
+
+/// Longest `host:port` that can be typed
+const MAX_ADDRESS_LEN: usize = 70;
+
 #[derive(Debug, Component)]
-struct LoginInput;
+struct NameField;
+
+#[derive(Debug, Component)]
+struct AddressField;
+
+#[derive(Debug, Component)]
+struct LoginStatus;
 
 #[derive(Debug, Component)]
 struct LoginUIMarker;
@@ -27,8 +45,16 @@ struct LoginUIMarker;
 impl Plugin for LoginPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(CurrentUserLogin(String::new()))
+            .insert_resource(LastServerAddress(
+                std::env::args().nth(1).unwrap_or_default(),
+            ))
             .add_systems(OnEnter(PetriState::Login), login_screen)
-            .add_systems(Update, login_input.run_if(in_state(PetriState::Login)))
+            .add_systems(
+                Update,
+                login_input
+                    .after(TextInputSet)
+                    .run_if(in_state(PetriState::Login)),
+            )
             .add_systems(
                 OnExit(PetriState::Login),
                 |mut cmd: Commands, login_entity: Query<Entity, With<LoginUIMarker>>| {
@@ -40,108 +66,199 @@ impl Plugin for LoginPlugin {
     }
 }
 
+/// Same characters as the server keeps in names
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, ' ' | '_' | '-')
+}
+
+fn is_address_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']')
+}
+
 /// crete ui entities for login screen
-fn login_screen(mut cmd: Commands, asset_server: Res<AssetServer>) {
+fn login_screen(
+    mut cmd: Commands,
+    asset_server: Res<AssetServer>,
+    login: Res<CurrentUserLogin>,
+    address: Res<LastServerAddress>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load("open-sans.ttf"),
+        font_size: 40.0,
+        ..default()
+    };
+    let small_text_style = TextStyle {
+        font_size: 20.0,
+        ..text_style.clone()
+    };
+
     cmd.spawn((Camera2dBundle::default(), LoginUIMarker));
-    let root = cmd
-        .spawn((
-            NodeBundle {
-                style: Style {
-                    width: Val::Percent(100.0),
-                    height: Val::Percent(100.0),
-                    justify_content: JustifyContent::Center,
-                    align_items: AlignItems::Center,
-                    flex_direction: FlexDirection::Column,
-                    ..default()
-                },
-                background_color: BackgroundColor(Color::DARK_GRAY),
+    cmd.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(12.0),
                 ..default()
             },
-            LoginUIMarker,
-        ))
-        .id();
-
-    let prompt = cmd
-        .spawn(
-            // Create a TextBundle that has a Text with a single section.
+            background_color: BackgroundColor(Color::DARK_GRAY),
+            ..default()
+        },
+        LoginUIMarker,
+    ))
+    .with_children(|parent| {
+        parent.spawn(
             TextBundle::from_section(
-                // Accepts a `String` or any type that converts into a `String`, such as `&str`
                 "Login",
                 TextStyle {
-                    // This font is loaded and will be used instead of the default font.
-                    font: asset_server.load("open-sans.ttf"),
                     font_size: 100.0,
-                    ..default()
+                    ..text_style.clone()
                 },
-            ) // Set the alignment of the Text
+            )
             .with_text_justify(JustifyText::Center),
-        )
-        .id();
+        );
+
+        spawn_field(
+            parent,
+            "Name",
+            TextInput::new(MAX_NAME_LEN)
+                .with_allowed(is_name_char)
+                .with_placeholder("Your name")
+                .with_value(&login.0)
+                .focused(),
+            NameField,
+            &text_style,
+        );
+        spawn_field(
+            parent,
+            "Server",
+            TextInput::new(MAX_ADDRESS_LEN)
+                .with_allowed(is_address_char)
+                .with_placeholder("Empty to browse servers")
+                .with_value(&address.0),
+            AddressField,
+            &text_style,
+        );
+
+        parent.spawn((
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    color: Color::ORANGE_RED,
+                    ..small_text_style.clone()
+                },
+            ),
+            LoginStatus,
+        ));
+        parent.spawn(TextBundle::from_section(
+            "Tab to switch fields, Enter to play, Escape to go back",
+            small_text_style,
+        ));
+    });
+}
 
-    let login_container = cmd
+fn spawn_field(
+    parent: &mut ChildBuilder,
+    label: &str,
+    input: TextInput,
+    marker: impl Component,
+    text_style: &TextStyle,
+) {
+    parent.spawn(TextBundle::from_section(
+        label,
+        TextStyle {
+            font_size: 20.0,
+            ..text_style.clone()
+        },
+    ));
+    parent
         .spawn((
             NodeBundle {
+                style: Style {
+                    min_width: Val::Px(500.0),
+                    padding: UiRect::horizontal(Val::Px(8.0)),
+                    ..default()
+                },
                 background_color: BackgroundColor(Color::DARK_GREEN),
                 ..default()
             },
             Outline {
-                width: Val::Px(6.),
-                offset: Val::Px(6.),
+                width: Val::Px(4.),
+                offset: Val::Px(4.),
                 color: Color::LIME_GREEN,
             },
         ))
-        .id();
-
-    let input = cmd
-        .spawn((
-            // Create a TextBundle that has a Text with a single section.
-            TextBundle::from_section(
-                // Accepts a `String` or any type that converts into a `String`, such as `&str`
-                "_",
-                TextStyle {
-                    // This font is loaded and will be used instead of the default font.
-                    font: asset_server.load("open-sans.ttf"),
-                    font_size: 100.0,
-                    ..default()
-                },
-            ) // Set the alignment of the Text
-            .with_text_justify(JustifyText::Center),
-            LoginInput,
-        ))
-        .id();
-
-    cmd.entity(login_container).add_child(input);
-    cmd.entity(root)
-        .add_child(prompt)
-        .add_child(login_container);
+        .with_children(|container| {
+            container.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                input,
+                marker,
+            ));
+        });
 }
 
-/// receive login credentials into the login field
+/// Tab, Up and Down switch between the fields, Escape goes back to the main menu.
+/// Enter checks the fields and moves on to the server browser,
+/// or straight to connecting when a server was typed in.
+#[allow(clippy::too_many_arguments)]
 fn login_input(
-    mut char_input_events: EventReader<ReceivedCharacter>,
-    mut keyboard_input_events: EventReader<KeyboardInput>,
-    mut login_label: Query<&mut Text, With<LoginInput>>,
+    mut commands: Commands,
+    key: Res<ButtonInput<KeyCode>>,
+    mut name_field: Query<&mut TextInput, (With<NameField>, Without<AddressField>)>,
+    mut address_field: Query<&mut TextInput, With<AddressField>>,
+    mut status: Query<&mut Text, With<LoginStatus>>,
     mut login: ResMut<CurrentUserLogin>,
+    mut last_address: ResMut<LastServerAddress>,
     mut next_state: ResMut<NextState<PetriState>>,
 ) {
-    for event in keyboard_input_events.read() {
-        match event.key_code {
-            KeyCode::Enter => {
-                if !login.0.trim().is_empty() {
-                    next_state.set(PetriState::Scene);
-                    return;
+    let (Ok(mut name), Ok(mut address)) =
+        (name_field.get_single_mut(), address_field.get_single_mut())
+    else {
+        return;
+    };
+
+    if key.any_just_pressed([KeyCode::Tab, KeyCode::ArrowUp, KeyCode::ArrowDown]) {
+        name.focused = !name.focused;
+        address.focused = !name.focused;
+    }
+    if key.just_pressed(KeyCode::Escape) {
+        next_state.set(PetriState::MainMenu);
+        return;
+    }
+    if !key.just_pressed(KeyCode::Enter) {
+        return;
+    }
+
+    let result = if name.value().trim().is_empty() {
+        Err("Enter a name".to_owned())
+    } else if address.value().is_empty() {
+        Ok(None)
+    } else {
+        address
+            .value()
+            .parse::<SelectedServer>()
+            .map(Some)
+            .map_err(|e| format!("{e:#}"))
+    };
+    match result {
+        Ok(server) => {
+            login.0 = name.value().trim().to_owned();
+            last_address.0 = address.value().to_owned();
+            match server {
+                Some(server) => {
+                    commands.insert_resource(server);
+                    next_state.set(PetriState::Connecting);
                 }
+                None => next_state.set(PetriState::ServerBrowser),
             }
-            KeyCode::Backspace => {
-                login.0.pop();
+        }
+        Err(error) => {
+            for mut text in &mut status {
+                text.sections[0].value.clone_from(&error);
             }
-            _ => {}
         }
     }
-    for event in char_input_events.read() {
-        login.0.push_str(event.char.as_str());
-    }
-    login_label.iter_mut().for_each(|mut l| {
-        l.sections[0].value = format!("{}_", login.0);
-    })
 }
@@ -1,7 +1,23 @@
 //! Client app
 
+mod camera_plugin;
+mod character_plugin;
+mod chat_plugin;
+mod config;
+mod connecting_plugin;
+mod controls_plugin;
+mod disconnected_plugin;
+mod input_plugin;
 mod login_plugin;
+mod main_menu_plugin;
+mod network_hud_plugin;
+mod player_model_plugin;
 mod plugin;
+mod raycast;
+mod scoreboard_plugin;
+mod server_browser_plugin;
+mod settings_plugin;
+mod text_input_plugin;
 
 use bevy::prelude::*;
 use bevy_replicon::{server::ServerPlugin, ReplicationPlugins};
@@ -13,8 +29,8 @@ fn main() {
     App::new()
         .add_plugins((
             DefaultPlugins.set(WindowPlugin {
+                // the present mode and window mode come from `settings_plugin::Settings`
                 primary_window: Some(Window {
-                    present_mode: bevy::window::PresentMode::AutoNoVsync,
                     title: "Petrichor IV".into(),
                     ..default()
                 }),
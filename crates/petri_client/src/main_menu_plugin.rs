@@ -0,0 +1,143 @@
+use bevy::{app::AppExit, prelude::*};
+
+use crate::plugin::PetriState;
+
+/// The first screen, leads to the game, the settings and the controls
+pub struct MainMenuPlugin;
+
+impl Plugin for MainMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SelectedMenuItem>()
+            .add_systems(OnEnter(PetriState::MainMenu), spawn_main_menu)
+            .add_systems(
+                Update,
+                (main_menu_input, update_main_menu)
+                    .chain()
+                    .run_if(in_state(PetriState::MainMenu)),
+            )
+            .add_systems(
+                OnExit(PetriState::MainMenu),
+                |mut cmd: Commands, ui: Query<Entity, With<MainMenuUIMarker>>| {
+                    ui.iter().for_each(|e| cmd.entity(e).despawn_recursive());
+                },
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MenuItem {
+    Play,
+    Settings,
+    Controls,
+    Quit,
+}
+
+const MENU_ITEMS: [MenuItem; 4] = [
+    MenuItem::Play,
+    MenuItem::Settings,
+    MenuItem::Controls,
+    MenuItem::Quit,
+];
+
+#[derive(Resource, Default, Debug)]
+struct SelectedMenuItem(usize);
+
+#[derive(Component)]
+struct MainMenuUIMarker;
+
+#[derive(Component)]
+struct MenuItems;
+
+fn spawn_main_menu(mut cmd: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("open-sans.ttf"),
+        font_size: 40.0,
+        ..default()
+    };
+    cmd.spawn((Camera2dBundle::default(), MainMenuUIMarker));
+    cmd.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(40.0),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::DARK_GRAY),
+            ..default()
+        },
+        MainMenuUIMarker,
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section(
+            "Petrichor IV",
+            TextStyle {
+                font_size: 100.0,
+                ..text_style.clone()
+            },
+        ));
+        parent.spawn((
+            TextBundle::from_section("", text_style).with_text_justify(JustifyText::Center),
+            MenuItems,
+        ));
+    });
+}
+
+/// Up and Down pick an item, Enter activates it
+fn main_menu_input(
+    key: Res<ButtonInput<KeyCode>>,
+    mut selected: ResMut<SelectedMenuItem>,
+    mut next_state: ResMut<NextState<PetriState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let count = MENU_ITEMS.len();
+    if key.just_pressed(KeyCode::ArrowUp) {
+        selected.0 = (selected.0 + count - 1) % count;
+    }
+    if key.just_pressed(KeyCode::ArrowDown) {
+        selected.0 = (selected.0 + 1) % count;
+    }
+    if key.just_pressed(KeyCode::Enter) {
+        match MENU_ITEMS[selected.0] {
+            MenuItem::Play => next_state.set(PetriState::Login),
+            MenuItem::Settings => next_state.set(PetriState::Settings),
+            MenuItem::Controls => next_state.set(PetriState::Controls),
+            MenuItem::Quit => {
+                exit.send(AppExit);
+            }
+        }
+    }
+}
+
+fn update_main_menu(
+    selected: Res<SelectedMenuItem>,
+    mut items: Query<(&mut Text, Ref<MenuItems>)>,
+) {
+    for (mut text, marker) in &mut items {
+        if !selected.is_changed() && !marker.is_added() {
+            continue;
+        }
+        let style = text.sections[0].style.clone();
+        text.sections = MENU_ITEMS
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let color = if i == selected.0 {
+                    Color::YELLOW
+                } else {
+                    Color::WHITE
+                };
+                TextSection::new(
+                    format!("{item:?}\n"),
+                    TextStyle {
+                        color,
+                        ..style.clone()
+                    },
+                )
+            })
+            .collect();
+    }
+}
@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_replicon::{prelude::*, renet::transport::NetcodeClientTransport};
+use petri_shared::{NetworkQuality, ServerShutdown};
+
+use crate::{
+    controls_plugin::Action,
+    plugin::{Me, PetriState},
+};
+
+pub struct NetworkHudPlugin;
+
+const HUD_FONT_SIZE: f32 = 14.0;
+/// How long without any packet until the server is considered unresponsive
+const STALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether the detailed network stats are shown, toggled with [`Action::NetworkStats`]
+#[derive(Resource, Default, Debug)]
+struct NetworkHudVisible(bool);
+
+/// Seconds left until the server shuts down, if it has announced it
+#[derive(Resource, Default, Debug)]
+pub struct ShutdownCountdown(pub Option<u32>);
+
+#[derive(Component)]
+struct NetworkHud;
+
+#[derive(Component)]
+struct ConnectionWarning;
+
+impl Plugin for NetworkHudPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetworkHudVisible>()
+            .init_resource::<ShutdownCountdown>()
+            .add_systems(OnEnter(PetriState::Scene), spawn_network_hud)
+            .add_systems(
+                Update,
+                (
+                    toggle_network_hud,
+                    receive_shutdown_notices,
+                    update_network_hud,
+                )
+                    .chain()
+                    .run_if(in_state(PetriState::Scene)),
+            )
+            .add_systems(OnExit(PetriState::Scene), despawn_network_hud);
+    }
+}
+
+fn spawn_network_hud(
+    mut cmd: Commands,
+    asset_server: Res<AssetServer>,
+    visible: Res<NetworkHudVisible>,
+) {
+    let text_style = TextStyle {
+        font: asset_server.load("open-sans.ttf"),
+        font_size: HUD_FONT_SIZE,
+        color: Color::WHITE,
+    };
+    cmd.spawn((
+        TextBundle::from_section("", text_style.clone()).with_style(Style {
+            // the choice is kept across reconnects
+            display: hud_display(&visible),
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.0),
+            top: Val::Px(10.0),
+            ..default()
+        }),
+        NetworkHud,
+    ));
+    cmd.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                color: Color::ORANGE_RED,
+                ..text_style
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            top: Val::Px(10.0),
+            ..default()
+        }),
+        ConnectionWarning,
+    ));
+}
+
+#[allow(clippy::type_complexity)]
+fn despawn_network_hud(
+    mut cmd: Commands,
+    hud: Query<Entity, Or<(With<NetworkHud>, With<ConnectionWarning>)>>,
+    mut countdown: ResMut<ShutdownCountdown>,
+) {
+    hud.iter().for_each(|e| cmd.entity(e).despawn_recursive());
+    countdown.0 = None;
+}
+
+fn toggle_network_hud(
+    actions: Res<ButtonInput<Action>>,
+    mut visible: ResMut<NetworkHudVisible>,
+    mut hud: Query<&mut Style, With<NetworkHud>>,
+) {
+    if actions.just_pressed(Action::NetworkStats) {
+        visible.0 = !visible.0;
+        for mut style in &mut hud {
+            style.display = hud_display(&visible);
+        }
+    }
+}
+
+fn hud_display(visible: &NetworkHudVisible) -> Display {
+    if visible.0 {
+        Display::Flex
+    } else {
+        Display::None
+    }
+}
+
+fn receive_shutdown_notices(
+    mut notices: EventReader<ServerShutdown>,
+    mut countdown: ResMut<ShutdownCountdown>,
+) {
+    if let Some(notice) = notices.read().last() {
+        countdown.0 = Some(notice.seconds_left);
+    }
+}
+
+fn update_network_hud(
+    quality: Query<&NetworkQuality, With<Me>>,
+    countdown: Res<ShutdownCountdown>,
+    replicon_tick: Res<RepliconTick>,
+    // a new one for every connection, so nothing carries over from the last one
+    transport: Option<Res<NetcodeClientTransport>>,
+    mut hud: Query<&mut Text, (With<NetworkHud>, Without<ConnectionWarning>)>,
+    mut warning: Query<&mut Text, With<ConnectionWarning>>,
+) {
+    // the server keeps sending packets even when nothing is replicated
+    let stalled = transport.is_some_and(|t| t.time_since_last_received_packet() > STALL_TIMEOUT);
+    let quality = quality.get_single().ok();
+
+    for mut text in &mut hud {
+        text.sections[0].value = match quality {
+            Some(q) => format!(
+                "RTT: {:.0} ms\nLoss: {:.1}%\nIn: {:.1} kbps\nOut: {:.1} kbps\nTick: {}",
+                q.rtt_ms,
+                q.packet_loss * 100.0,
+                q.sent_kbps,
+                q.received_kbps,
+                replicon_tick.get(),
+            ),
+            None => format!("Tick: {}", replicon_tick.get()),
+        };
+    }
+
+    let message = if let Some(seconds_left) = countdown.0 {
+        format!("Server is shutting down in {seconds_left} s")
+    } else if stalled {
+        "Server is not responding".to_owned()
+    } else if let Some(q) = quality.filter(|q| q.is_degraded()) {
+        if q.rtt_ms > NetworkQuality::HIGH_RTT_MS {
+            format!("High latency: {:.0} ms", q.rtt_ms)
+        } else {
+            format!("Packet loss: {:.1}%", q.packet_loss * 100.0)
+        }
+    } else {
+        String::new()
+    };
+    for mut text in &mut warning {
+        if text.sections[0].value != message {
+            text.sections[0].value.clone_from(&message);
+        }
+    }
+}
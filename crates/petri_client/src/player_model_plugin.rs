@@ -0,0 +1,244 @@
+use bevy::{ecs::system::EntityCommands, prelude::*};
+use petri_shared::{get_player_capsule_size, ReplicatedAim, PLAYER_HEIGHT};
+
+use crate::{
+    camera_plugin::{CameraMode, LookAngles},
+    controls_plugin::Action,
+    plugin::{Me, PetriState},
+};
+
+/// Builds player models out of simple shapes and turns them where players aim
+pub struct PlayerModelPlugin;
+
+impl Plugin for PlayerModelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerModelAssets>()
+            .init_resource::<ShowAimRays>()
+            .add_systems(
+                Update,
+                (
+                    aim_player_models,
+                    show_first_person_parts,
+                    toggle_aim_rays,
+                    draw_aim_rays.run_if(|show: Res<ShowAimRays>| show.0),
+                )
+                    .run_if(in_state(PetriState::Scene)),
+            );
+    }
+}
+
+/// Meshes shared by every player model, players differ only in their material
+#[derive(Resource)]
+pub struct PlayerModelAssets {
+    torso: Handle<Mesh>,
+    head: Handle<Mesh>,
+    visor: Handle<Mesh>,
+    arm: Handle<Mesh>,
+    visor_material: Handle<StandardMaterial>,
+}
+
+impl FromWorld for PlayerModelAssets {
+    fn from_world(world: &mut World) -> Self {
+        let (capsule_diameter, capsule_segment_half_height) = get_player_capsule_size();
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        let torso = meshes.add(Capsule3d::new(
+            capsule_diameter / 2.0,
+            capsule_segment_half_height * 2.0,
+        ));
+        let head = meshes.add(Sphere::new(HEAD_RADIUS));
+        let visor = meshes.add(Cuboid::new(0.3, 0.08, 0.1));
+        let arm = meshes.add(Cuboid::new(0.1, 0.1, 0.5));
+        let visor_material = world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(Color::rgb(0.05, 0.05, 0.1));
+        Self {
+            torso,
+            head,
+            visor,
+            arm,
+            visor_material,
+        }
+    }
+}
+
+const HEAD_RADIUS: f32 = 0.25;
+
+/// Every mesh of a player model, so the camera can see through them
+#[derive(Component)]
+pub struct PlayerPart;
+
+/// Only seen from the player's own eyes, like the arms
+#[derive(Component)]
+struct FirstPersonOnly;
+
+/// Hidden from the player's own eyes, like the head the camera is in
+#[derive(Component)]
+struct HiddenInFirstPerson;
+
+/// The parts of another player's model that turn with their [`ReplicatedAim`]
+#[derive(Component)]
+struct PlayerModel {
+    /// Turns left and right
+    body: Entity,
+    /// Looks up and down, on the body
+    head: Entity,
+}
+
+/// Draws where every player aims, toggled with [`Action::AimRays`]
+#[derive(Resource, Default, Debug)]
+struct ShowAimRays(bool);
+
+/// The model of someone else, turned by [`aim_player_models`]
+pub fn spawn_player_model(
+    entity_builder: &mut EntityCommands,
+    assets: &PlayerModelAssets,
+    material: Handle<StandardMaterial>,
+) {
+    entity_builder.insert(SpatialBundle::default());
+    let mut head = None;
+    let mut body = None;
+    entity_builder.with_children(|parent| {
+        let mut body_builder = parent.spawn(SpatialBundle::default());
+        body_builder.with_children(|body| {
+            spawn_torso(body, assets, material.clone());
+            let mut head_builder = body.spawn(SpatialBundle::from_transform(head_transform()));
+            head_builder.with_children(|head| spawn_head(head, assets, material));
+            head = Some(head_builder.id());
+        });
+        body = Some(body_builder.id());
+    });
+    if let (Some(body), Some(head)) = (body, head) {
+        entity_builder.insert(PlayerModel { body, head });
+    }
+}
+
+/// Where the head goes on the body, the same height as the eyes
+pub fn head_transform() -> Transform {
+    Transform::from_xyz(0.0, PLAYER_HEIGHT, 0.0)
+}
+
+pub fn spawn_torso(
+    parent: &mut ChildBuilder,
+    assets: &PlayerModelAssets,
+    material: Handle<StandardMaterial>,
+) {
+    parent.spawn((
+        PbrBundle {
+            mesh: assets.torso.clone(),
+            material,
+            ..default()
+        },
+        PlayerPart,
+    ));
+}
+
+/// The head with a visor on the side it's looking at
+pub fn spawn_head(
+    parent: &mut ChildBuilder,
+    assets: &PlayerModelAssets,
+    material: Handle<StandardMaterial>,
+) {
+    parent
+        .spawn((
+            PbrBundle {
+                mesh: assets.head.clone(),
+                material,
+                ..default()
+            },
+            PlayerPart,
+            HiddenInFirstPerson,
+        ))
+        .with_children(|head| {
+            head.spawn((
+                PbrBundle {
+                    mesh: assets.visor.clone(),
+                    material: assets.visor_material.clone(),
+                    transform: Transform::from_xyz(0.0, 0.03, -HEAD_RADIUS + 0.03),
+                    ..default()
+                },
+                PlayerPart,
+            ));
+        });
+}
+
+/// Arms reaching out in front of the eyes, only seen in first person
+pub fn spawn_arms(
+    parent: &mut ChildBuilder,
+    assets: &PlayerModelAssets,
+    material: Handle<StandardMaterial>,
+) {
+    for side in [-1.0, 1.0] {
+        parent.spawn((
+            PbrBundle {
+                mesh: assets.arm.clone(),
+                material: material.clone(),
+                transform: Transform::from_xyz(side * 0.25, -0.25, -0.35),
+                ..default()
+            },
+            PlayerPart,
+            FirstPersonOnly,
+        ));
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn aim_player_models(
+    players: Query<
+        (&ReplicatedAim, &PlayerModel),
+        Or<(Changed<ReplicatedAim>, Added<PlayerModel>)>,
+    >,
+    mut transforms: Query<&mut Transform>,
+) {
+    for (aim, model) in &players {
+        let look = LookAngles::looking(aim.0);
+        if let Ok(mut body) = transforms.get_mut(model.body) {
+            body.rotation = look.body_rotation();
+        }
+        if let Ok(mut head) = transforms.get_mut(model.head) {
+            head.rotation = look.head_rotation();
+        }
+    }
+}
+
+/// Swaps the head for the arms when looking through the player's own eyes
+#[allow(clippy::type_complexity)]
+fn show_first_person_parts(
+    mode: Res<CameraMode>,
+    mut first_person_only: Query<&mut Visibility, With<FirstPersonOnly>>,
+    mut hidden_in_first_person: Query<
+        &mut Visibility,
+        (With<HiddenInFirstPerson>, Without<FirstPersonOnly>),
+    >,
+    me: Query<Entity, With<Me>>,
+    children: Query<&Children>,
+) {
+    let first_person = *mode == CameraMode::FirstPerson;
+    let (shown, hidden) = match first_person {
+        true => (Visibility::Inherited, Visibility::Hidden),
+        false => (Visibility::Hidden, Visibility::Inherited),
+    };
+    for mut visibility in &mut first_person_only {
+        visibility.set_if_neq(shown);
+    }
+    // only our own head, everyone else's is always seen
+    for me in &me {
+        for part in children.iter_descendants(me) {
+            if let Ok(mut visibility) = hidden_in_first_person.get_mut(part) {
+                visibility.set_if_neq(hidden);
+            }
+        }
+    }
+}
+
+fn toggle_aim_rays(actions: Res<ButtonInput<Action>>, mut show: ResMut<ShowAimRays>) {
+    if actions.just_pressed(Action::AimRays) {
+        show.0 = !show.0;
+    }
+}
+
+fn draw_aim_rays(players: Query<(&GlobalTransform, &ReplicatedAim)>, mut gizmos: Gizmos) {
+    for (transform, aim) in &players {
+        let start = transform.translation() + Vec3::Y * PLAYER_HEIGHT;
+        gizmos.ray(start, aim.0 * 1.5, Color::VIOLET);
+    }
+}
@@ -1,59 +1,94 @@
-use std::{
-    net::{Ipv4Addr, SocketAddr, UdpSocket},
-    time::{Duration, SystemTime},
-};
+use std::time::Duration;
 
 use bevy::{
     core_pipeline::Skybox,
     ecs::{query::QueryEntityError, system::EntityCommands},
-    input::mouse::MouseMotion,
     prelude::*,
     time::common_conditions::on_timer,
     window::CursorGrabMode,
 };
 use bevy_replicon::{
-    client_just_connected,
-    prelude::{NetworkChannels, RenetClient},
-    renet::{
-        transport::{ClientAuthentication, NetcodeClientTransport},
-        ConnectionConfig,
-    },
+    prelude::{RenetClient, Replication},
+    renet::transport::NetcodeClientTransport,
 };
 use petri_shared::{
-    get_player_capsule_size, AdminCommand, Aim, Appearance, MoveDirection, Player, ReplicatedAim,
-    ReplicatedPos, SetName, Tint, PLAYER_HEIGHT,
+    get_player_capsule_size, AdminCommand, Appearance, MoveDirection, NameAccepted, Player,
+    ReplicatedPos, SetName, Tint,
 };
 
-use crate::login_plugin::{CurrentUserLogin, LoginPlugin};
+use crate::{
+    camera_plugin::{camera_moves_player, Body, CameraPlugin, LookAngles, PlayerCamera},
+    character_plugin::{spawn_character, CharacterPlugin},
+    chat_plugin::{chat_is_closed, ChatPlugin},
+    connecting_plugin::ConnectingPlugin,
+    controls_plugin::{gamepad_stick, Action, ControlsPlugin, Stick},
+    disconnected_plugin::DisconnectedPlugin,
+    input_plugin::InputPlugin,
+    login_plugin::{CurrentUserLogin, LoginPlugin},
+    main_menu_plugin::MainMenuPlugin,
+    network_hud_plugin::NetworkHudPlugin,
+    player_model_plugin::{
+        head_transform, spawn_arms, spawn_head, spawn_player_model, spawn_torso, PlayerModelAssets,
+        PlayerModelPlugin,
+    },
+    scoreboard_plugin::ScoreboardPlugin,
+    server_browser_plugin::ServerBrowserPlugin,
+    settings_plugin::SettingsPlugin,
+    text_input_plugin::TextInputPlugin,
+};
 
 pub struct PetriClientPlugin;
 
 #[derive(States, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum PetriState {
+    MainMenu,
+    Settings,
+    Controls,
     Login,
+    /// Picking a server to play on
+    ServerBrowser,
+    /// Connecting to the server and loading the assets of the scene
+    Connecting,
     Scene,
+    /// The connection was lost, see [`crate::disconnected_plugin::DisconnectReason`]
+    Disconnected,
 }
 
 impl Plugin for PetriClientPlugin {
     fn build(&self, app: &mut App) {
         let player_has_spawned = any_with_component::<Eyes>;
 
-        app.insert_state(PetriState::Login)
+        app.insert_state(PetriState::MainMenu)
+            .add_plugins(TextInputPlugin)
+            .add_plugins(SettingsPlugin)
+            .add_plugins(ControlsPlugin)
+            .add_plugins(CameraPlugin)
+            .add_plugins(PlayerModelPlugin)
+            .add_plugins(CharacterPlugin)
+            .add_plugins(MainMenuPlugin)
             .add_plugins(LoginPlugin)
-            .add_systems(
-                OnEnter(PetriState::Scene),
-                (setup_scene, setup_connection.map(Result::unwrap)),
-            )
+            .add_plugins(ChatPlugin)
+            .add_plugins(InputPlugin)
+            .add_plugins(ScoreboardPlugin)
+            .add_plugins(NetworkHudPlugin)
+            .add_plugins(ServerBrowserPlugin)
+            .add_plugins(ConnectingPlugin)
+            .add_plugins(DisconnectedPlugin)
+            // the connection is made before entering the scene
+            .add_systems(OnEnter(PetriState::Scene), (setup_scene, send_name))
             .add_systems(
                 Update,
                 (
                     grab_mouse,
-                    send_name.run_if(client_just_connected),
+                    receive_accepted_name,
                     (
-                        aim,
                         hud_update_entity_name_plaques,
-                        send_movement,
-                        create_wall,
+                        (
+                            send_movement.run_if(camera_moves_player),
+                            create_wall,
+                            undo_spawn,
+                        )
+                            .run_if(chat_is_closed),
                     )
                         .run_if(player_has_spawned),
                     hydrate_entities,
@@ -62,8 +97,28 @@ impl Plugin for PetriClientPlugin {
                 )
                     .run_if(in_state(PetriState::Scene)),
             )
-            .add_systems(OnExit(PetriState::Scene), || todo!("Clean up world"));
+            .add_systems(OnExit(PetriState::Scene), clean_up_scene);
 
+        /// Despawns everything from the server and the scene around it
+        /// and forgets the connection
+        #[allow(clippy::type_complexity)]
+        fn clean_up_scene(
+            mut commands: Commands,
+            entities: Query<Entity, Or<(With<Replication>, With<SceneMarker>)>>,
+            labels: Query<Entity, With<NameLabelOf>>,
+        ) {
+            for label in &labels {
+                commands.entity(label).despawn_recursive();
+            }
+            for entity in &entities {
+                commands.entity(entity).despawn_recursive();
+            }
+            commands.remove_resource::<NetcodeClientTransport>();
+            commands.remove_resource::<RenetClient>();
+            commands.remove_resource::<MyPlayerId>();
+        }
+
+        #[allow(clippy::too_many_arguments)]
         fn hydrate_entities(
             mut commands: Commands,
             mut meshes: ResMut<Assets<Mesh>>,
@@ -73,94 +128,100 @@ impl Plugin for PetriClientPlugin {
             player_id: Query<&Player>,
             my_player_id: Res<MyPlayerId>,
             asset_server: Res<AssetServer>,
+            player_model: Res<PlayerModelAssets>,
         ) {
             for (entity, tint, appearnce) in dry_entities.iter() {
                 info!("Adding mesh to {:?}", names.get(entity));
-                let (capsule_diameter, capsule_segment_half_height) = get_player_capsule_size();
                 let mut entity_builder = commands.entity(entity);
+                let material = materials.add(tint.0);
                 info!("Player id: {:?}", player_id.get(entity));
-                if player_id.get(entity).map(|p| p.0.raw()) == Ok(my_player_id.0) {
-                    spawn_me(&mut entity_builder, &asset_server);
-                } else {
-                    entity_builder.insert(PbrBundle {
-                        mesh: match appearnce {
-                            Appearance::Capsule => meshes.add(Capsule3d::new(
+                // our own character model would be in the way of the camera,
+                // so we always get the player model
+                match (player_id.get(entity), appearnce) {
+                    (Ok(player), _) if player.0.raw() == my_player_id.0 => {
+                        spawn_me(&mut entity_builder, &asset_server, &player_model, material);
+                    }
+                    (_, Appearance::Character(model)) => {
+                        spawn_character(&mut entity_builder, &asset_server, model, material);
+                    }
+                    (Ok(_), _) => spawn_player_model(&mut entity_builder, &player_model, material),
+                    (Err(_), Appearance::Capsule) => {
+                        let (capsule_diameter, capsule_segment_half_height) =
+                            get_player_capsule_size();
+                        entity_builder.insert(PbrBundle {
+                            mesh: meshes.add(Capsule3d::new(
                                 capsule_diameter / 2.0,
                                 capsule_segment_half_height * 2.0,
                             )),
-                            Appearance::Box => meshes.add(Cuboid::new(1.0, 1.0, 1.0)),
-                        },
-                        material: materials.add(tint.0),
-                        transform: Transform::from_xyz(0.0, 0.5, 0.0),
-                        ..default()
-                    });
+                            material,
+                            ..default()
+                        });
+                    }
+                    (Err(_), Appearance::Box) => {
+                        entity_builder.insert(PbrBundle {
+                            mesh: meshes.add(Cuboid::new(1.0, 1.0, 1.0)),
+                            material,
+                            ..default()
+                        });
+                    }
                 }
             }
         }
 
-        fn spawn_me(entity_builder: &mut EntityCommands, asset_server: &Res<AssetServer>) {
-            entity_builder.insert((Me, TransformBundle::default()));
+        /// The local player, with a torso on the body and a head and arms on the eyes
+        fn spawn_me(
+            entity_builder: &mut EntityCommands,
+            asset_server: &Res<AssetServer>,
+            player_model: &PlayerModelAssets,
+            material: Handle<StandardMaterial>,
+        ) {
+            let look = LookAngles::default();
+            entity_builder.insert((Me, look, SpatialBundle::default()));
 
+            // the body turns left and right, the eyes on it look up and down
             entity_builder.with_children(|parent| {
-                parent.spawn(
-                    // camera
-                    (
-                        Eyes,
-                        Camera3dBundle {
-                            transform: Transform::from_xyz(0.0, PLAYER_HEIGHT, 0.0).looking_at(
-                                Vec3 {
-                                    x: 0.0,
-                                    y: PLAYER_HEIGHT,
-                                    z: 1.0,
-                                },
-                                Vec3::Y,
+                parent
+                    .spawn((
+                        Body,
+                        SpatialBundle::from_transform(Transform::from_rotation(
+                            look.body_rotation(),
+                        )),
+                    ))
+                    .with_children(|body| {
+                        spawn_torso(body, player_model, material.clone());
+                        body.spawn((
+                            Eyes,
+                            SpatialBundle::from_transform(
+                                head_transform().with_rotation(look.head_rotation()),
                             ),
-                            ..default()
-                        },
-                        Skybox {
-                            image: asset_server.load("specular.ktx2"),
-                            brightness: 150.0,
-                        },
-                        EnvironmentMapLight {
-                            specular_map: asset_server.load("specular.ktx2"),
-                            diffuse_map: asset_server.load("diffuse.ktx2"),
-                            intensity: 150.0,
-                        },
-                    ),
-                );
+                        ))
+                        .with_children(|eyes| {
+                            spawn_head(eyes, player_model, material.clone());
+                            spawn_arms(eyes, player_model, material);
+                        });
+                    });
             });
-        }
 
-        fn move_entities_from_network(
-            mut players: Query<(&mut Transform, &ReplicatedPos, &ReplicatedAim)>,
-            // debugging
-            mut gizmos: Gizmos,
-        ) {
-            for (mut t, p, a) in &mut players {
-                *t = p.0.into();
-                let start = t.translation + Vec3::Y * PLAYER_HEIGHT;
-                gizmos.ray(start, a.0 * 1.5, Color::VIOLET);
-            }
+            entity_builder.commands().spawn((
+                PlayerCamera,
+                Camera3dBundle::default(),
+                Skybox {
+                    image: asset_server.load("specular.ktx2"),
+                    brightness: 150.0,
+                },
+                EnvironmentMapLight {
+                    specular_map: asset_server.load("specular.ktx2"),
+                    diffuse_map: asset_server.load("diffuse.ktx2"),
+                    intensity: 150.0,
+                },
+                SceneMarker,
+            ));
         }
 
-        fn aim(
-            mut mouse_motion_events: EventReader<MouseMotion>,
-            mut eyes: Query<&mut Transform, With<Eyes>>,
-            mut events: EventWriter<Aim>,
-            windows: Query<&mut Window>,
-        ) {
-            // only aim when cursor is grabbed
-            if windows.single().cursor.visible {
-                return;
+        fn move_entities_from_network(mut entities: Query<(&mut Transform, &ReplicatedPos)>) {
+            for (mut t, p) in &mut entities {
+                *t = p.0.into();
             }
-            let sensitivity = 0.001;
-
-            let mut transform = eyes.single_mut();
-            let delta = mouse_motion_events.read().map(|e| e.delta).sum::<Vec2>();
-            // FIXME: limit X turn angle
-            transform.rotate_y(-delta.x * sensitivity);
-            transform.rotate_local_x(-delta.y * sensitivity);
-            events.send(Aim(transform.forward()));
         }
 
         /// load the 3d scene
@@ -170,63 +231,25 @@ impl Plugin for PetriClientPlugin {
             mut materials: ResMut<Assets<StandardMaterial>>,
             asset_server: Res<AssetServer>,
         ) {
-            commands.spawn(SceneBundle {
-                scene: asset_server.load("petrichor4-intro.glb#Scene0"),
-                ..default()
-            });
+            commands.spawn((
+                SceneBundle {
+                    scene: asset_server.load("petrichor4-intro.glb#Scene0"),
+                    ..default()
+                },
+                SceneMarker,
+            ));
             // circular base
-            commands.spawn(PbrBundle {
-                mesh: meshes.add(Circle::new(4.0)),
-                material: materials.add(Color::WHITE),
-                transform: Transform::from_rotation(Quat::from_rotation_x(
-                    -std::f32::consts::FRAC_PI_2,
-                )),
-                ..default()
-            });
-        }
-
-        // Player id of the player who is playing this instance of the game
-        #[derive(Resource)]
-        struct MyPlayerId(u64);
-
-        fn setup_connection(
-            mut commands: Commands,
-            network_channels: Res<NetworkChannels>,
-        ) -> anyhow::Result<()> {
-            let server_channels_config = network_channels.get_server_configs();
-            let client_channels_config = network_channels.get_client_configs();
-
-            let client = RenetClient::new(ConnectionConfig {
-                server_channels_config,
-                client_channels_config,
-                ..default()
-            });
-
-            let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
-            let client_id = current_time.as_millis() as u64;
-
-            let addr = std::env::args()
-                .nth(1)
-                .map(|v| dns_lookup::lookup_host(&v).unwrap()[0])
-                .unwrap_or(Ipv4Addr::LOCALHOST.into());
-            let server_addr = SocketAddr::new(addr, 8989);
-            info!("Connecting to {server_addr:?}...");
-
-            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
-            let authentication = ClientAuthentication::Unsecure {
-                client_id,
-                protocol_id: 0,
-                server_addr,
-                user_data: None,
-            };
-
-            let transport = NetcodeClientTransport::new(current_time, authentication, socket)?;
-
-            commands.insert_resource(MyPlayerId(client_id));
-            commands.insert_resource(client);
-            commands.insert_resource(transport);
-
-            Ok(())
+            commands.spawn((
+                PbrBundle {
+                    mesh: meshes.add(Circle::new(4.0)),
+                    material: materials.add(Color::WHITE),
+                    transform: Transform::from_rotation(Quat::from_rotation_x(
+                        -std::f32::consts::FRAC_PI_2,
+                    )),
+                    ..default()
+                },
+                SceneMarker,
+            ));
         }
 
         fn send_name(mut set_name: EventWriter<SetName>, login: Res<CurrentUserLogin>) {
@@ -234,20 +257,44 @@ impl Plugin for PetriClientPlugin {
             set_name.send(SetName(login.0.clone()));
         }
 
+        /// The server may have cleaned up or deduplicated the name we asked for
+        fn receive_accepted_name(
+            mut events: EventReader<NameAccepted>,
+            mut login: ResMut<CurrentUserLogin>,
+        ) {
+            for NameAccepted(name) in events.read() {
+                if *name != login.0 {
+                    info!("Server renamed us from {:?} to {name:?}", login.0);
+                }
+                login.0.clone_from(name);
+            }
+        }
+
         #[derive(Component)]
         struct PlayerNameLabel(Entity);
 
-        // FIXME: this doesn't clean up labels on disconnects
-        /// creates labels and updates their positions
+        /// Put on a label node, points back at the entity the label is for
+        #[derive(Component)]
+        struct NameLabelOf(Entity);
+
+        /// creates labels, updates their positions
+        /// and despawns the ones whose entity has gone away
+        #[allow(clippy::too_many_arguments)]
         fn hud_update_entity_name_plaques(
             mut commands: Commands,
             named_entities: Query<(Entity, &Name, &GlobalTransform), Without<Me>>,
             mut labels: Query<&mut PlayerNameLabel>,
+            label_nodes: Query<(Entity, &NameLabelOf)>,
             mut styles: Query<&mut Style>,
             asset_server: Res<AssetServer>,
-            camera: Query<(&Camera, &GlobalTransform), With<Eyes>>,
+            camera: Query<(&Camera, &GlobalTransform), With<PlayerCamera>>,
         ) {
             let (camera, camera_transform) = camera.single();
+            for (node, NameLabelOf(owner)) in &label_nodes {
+                if !labels.contains(*owner) {
+                    commands.entity(node).despawn_recursive();
+                }
+            }
             for (entity, name, transform) in &named_entities {
                 // FIXME: update and create in a single step
                 match labels.get_mut(entity) {
@@ -280,6 +327,7 @@ impl Plugin for PetriClientPlugin {
                                 },
                                 ..default()
                             })
+                            .insert(NameLabelOf(entity))
                             .id();
                         commands.entity(entity).insert(PlayerNameLabel(node));
                     }
@@ -290,34 +338,45 @@ impl Plugin for PetriClientPlugin {
     }
 }
 
-/// Marks the entity with the camera that represents players eyes
+/// Player id of the player who is playing this instance of the game
+#[derive(Resource)]
+pub struct MyPlayerId(pub u64);
+
+/// Marks local entities that make up the level, despawned when leaving the scene
 #[derive(Component)]
-struct Eyes;
+struct SceneMarker;
+
+/// Marks the entity that represents the player's eyes, where the first person camera goes
+#[derive(Component)]
+pub struct Eyes;
 
 /// Marks the entity that represents the player
 #[derive(Component)]
-struct Me;
+pub struct Me;
 
 fn send_movement(
     mut writer: EventWriter<MoveDirection>,
-    input: Res<ButtonInput<KeyCode>>,
-    eyes: Query<&GlobalTransform, With<Eyes>>,
+    actions: Res<ButtonInput<Action>>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    look: Query<&LookAngles, With<Me>>,
 ) {
-    let pos = eyes.single();
-    let forward = pos.forward();
+    // along the ground, looking down doesn't slow us down
+    let forward = look.single().forward();
 
-    let mut direction = Vec2::default();
     // +Y is right
     // +X is forward
-    static KEYBINDINGS: &[(KeyCode, Vec2)] = &[
-        (KeyCode::KeyA, Vec2::new(0.0, -1.0)),
-        (KeyCode::KeyD, Vec2::new(0.0, 1.0)),
-        (KeyCode::KeyW, Vec2::new(1.0, 0.0)),
-        (KeyCode::KeyS, Vec2::new(-1.0, 0.0)),
+    static MOVEMENT_ACTIONS: &[(Action, Vec2)] = &[
+        (Action::MoveLeft, Vec2::new(0.0, -1.0)),
+        (Action::MoveRight, Vec2::new(0.0, 1.0)),
+        (Action::MoveForward, Vec2::new(1.0, 0.0)),
+        (Action::MoveBack, Vec2::new(-1.0, 0.0)),
     ];
 
-    for (key, dir) in KEYBINDINGS {
-        if input.pressed(*key) {
+    let stick = gamepad_stick(&gamepads, &axes, Stick::Left);
+    let mut direction = Vec2::new(stick.y, stick.x);
+    for (action, dir) in MOVEMENT_ACTIONS {
+        if actions.pressed(*action) {
             direction += *dir;
         }
     }
@@ -332,21 +391,17 @@ fn send_movement(
     }
 }
 
-/// This system grabs the mouse when the left mouse button is pressed
-/// and releases it when the escape key is pressed
-fn grab_mouse(
-    mut windows: Query<&mut Window>,
-    mouse: Res<ButtonInput<MouseButton>>,
-    key: Res<ButtonInput<KeyCode>>,
-) {
+/// This system grabs the mouse on [`Action::GrabCursor`], left click by default,
+/// and releases it on [`Action::ReleaseCursor`], Escape by default
+fn grab_mouse(mut windows: Query<&mut Window>, actions: Res<ButtonInput<Action>>) {
     let mut window = windows.single_mut();
 
-    if mouse.just_pressed(MouseButton::Left) {
+    if actions.just_pressed(Action::GrabCursor) {
         window.cursor.visible = false;
         window.cursor.grab_mode = CursorGrabMode::Locked;
     }
 
-    if key.just_pressed(KeyCode::Escape) {
+    if actions.just_pressed(Action::ReleaseCursor) {
         window.cursor.visible = true;
         window.cursor.grab_mode = CursorGrabMode::None;
     }
@@ -360,13 +415,19 @@ fn log_entity_names(e: Query<&Name>) {
 }
 
 fn create_wall(
-    mouse: Res<ButtonInput<MouseButton>>,
+    actions: Res<ButtonInput<Action>>,
     eyes: Query<&GlobalTransform, With<Eyes>>,
     mut events: EventWriter<AdminCommand>,
 ) {
-    if mouse.just_pressed(MouseButton::Right) {
+    if actions.just_pressed(Action::BuildWall) {
         let eyes = eyes.single();
         let at = eyes.translation() + eyes.forward() * 3.0;
         events.send(AdminCommand::SpawnBoxWall { side_size: 3, at });
     }
 }
+
+fn undo_spawn(actions: Res<ButtonInput<Action>>, mut events: EventWriter<AdminCommand>) {
+    if actions.just_pressed(Action::UndoBuild) {
+        events.send(AdminCommand::UndoLastSpawn);
+    }
+}
@@ -0,0 +1,228 @@
+//! Rays against rendered meshes, the client has no physics of its own
+
+use bevy::{
+    prelude::*,
+    render::{mesh::PrimitiveTopology, primitives::Aabb},
+};
+
+/// Distance along `ray` to the closest triangle of `targets` within `max_distance`
+pub fn cast_ray<'a>(
+    ray: Ray3d,
+    max_distance: f32,
+    meshes: &Assets<Mesh>,
+    targets: impl IntoIterator<Item = (&'a Handle<Mesh>, &'a GlobalTransform, &'a Aabb)>,
+) -> Option<f32> {
+    let mut closest = None;
+    for (handle, transform, aabb) in targets {
+        let Some(mesh) = meshes.get(handle) else {
+            continue;
+        };
+        // work in the mesh's space, the ray end keeps distances right when it's scaled
+        let to_local = transform.affine().inverse();
+        let origin = to_local.transform_point3(ray.origin);
+        let end = to_local.transform_point3(ray.get_point(closest.unwrap_or(max_distance)));
+        let Some(direction) = (end - origin).try_normalize() else {
+            continue;
+        };
+        let length = origin.distance(end);
+
+        let min = Vec3::from(aabb.center - aabb.half_extents);
+        let max = Vec3::from(aabb.center + aabb.half_extents);
+        if !hits_box(origin, direction, length, min, max) {
+            continue;
+        }
+        if let Some(hit) = hit_mesh(mesh, origin, direction, length) {
+            let world_hit = transform.transform_point(origin + direction * hit);
+            closest = Some(ray.origin.distance(world_hit));
+        }
+    }
+    closest
+}
+
+/// Slab test, whether the segment from `origin` touches the box
+fn hits_box(origin: Vec3, direction: Vec3, length: f32, min: Vec3, max: Vec3) -> bool {
+    let inverse = direction.recip();
+    let t1 = (min - origin) * inverse;
+    let t2 = (max - origin) * inverse;
+    let near = t1.min(t2).max_element();
+    let far = t1.max(t2).min_element();
+    near <= far && far >= 0.0 && near <= length
+}
+
+/// Closest triangle hit within `length`, in mesh space.
+/// Only called for meshes whose bounding box the ray touches.
+fn hit_mesh(mesh: &Mesh, origin: Vec3, direction: Vec3, length: f32) -> Option<f32> {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        return None;
+    }
+    let positions = mesh.attribute(Mesh::ATTRIBUTE_POSITION)?.as_float3()?;
+    // this runs every frame for the third person camera, so nothing is collected
+    let indexed = mesh.indices().map(|indices| indices.iter());
+    let unindexed = indexed.is_none().then_some(0..positions.len());
+    let mut indices = indexed
+        .into_iter()
+        .flatten()
+        .chain(unindexed.into_iter().flatten());
+
+    let mut closest: Option<f32> = None;
+    while let (Some(a), Some(b), Some(c)) = (indices.next(), indices.next(), indices.next()) {
+        let [a, b, c] = [a, b, c].map(|i| positions.get(i));
+        let (Some(a), Some(b), Some(c)) = (a, b, c) else {
+            continue;
+        };
+        let triangle = [Vec3::from(*a), Vec3::from(*b), Vec3::from(*c)];
+        if let Some(t) = hit_triangle(origin, direction, triangle) {
+            if t <= length && closest.map_or(true, |c| t < c) {
+                closest = Some(t);
+            }
+        }
+    }
+    closest
+}
+
+/// Möller–Trumbore, hits both sides of the triangle
+fn hit_triangle(origin: Vec3, direction: Vec3, [a, b, c]: [Vec3; 3]) -> Option<f32> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        // parallel to the triangle
+        return None;
+    }
+    let inverse = determinant.recip();
+    let s = origin - a;
+    let u = s.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) * inverse;
+    (t >= 0.0).then_some(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: [Vec3; 3] = [
+        Vec3::new(-1.0, -1.0, 0.0),
+        Vec3::new(1.0, -1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    ];
+
+    #[test]
+    fn triangle_is_hit_from_both_sides() {
+        assert_eq!(
+            hit_triangle(Vec3::Z * 2.0, Vec3::NEG_Z, TRIANGLE),
+            Some(2.0)
+        );
+        assert_eq!(
+            hit_triangle(Vec3::NEG_Z * 3.0, Vec3::Z, TRIANGLE),
+            Some(3.0)
+        );
+    }
+
+    #[test]
+    fn triangle_misses() {
+        // beside it, behind the ray and along its plane
+        assert_eq!(
+            hit_triangle(Vec3::new(2.0, 0.0, 2.0), Vec3::NEG_Z, TRIANGLE),
+            None
+        );
+        assert_eq!(hit_triangle(Vec3::Z * 2.0, Vec3::Z, TRIANGLE), None);
+        assert_eq!(
+            hit_triangle(Vec3::new(-5.0, 0.0, 0.0), Vec3::X, TRIANGLE),
+            None
+        );
+    }
+
+    #[test]
+    fn box_slab_test() {
+        let (min, max) = (Vec3::splat(-1.0), Vec3::splat(1.0));
+        assert!(hits_box(Vec3::Z * 5.0, Vec3::NEG_Z, 10.0, min, max));
+        // starting inside
+        assert!(hits_box(Vec3::ZERO, Vec3::X, 0.1, min, max));
+        // too short, pointing away, and beside it
+        assert!(!hits_box(Vec3::Z * 5.0, Vec3::NEG_Z, 3.0, min, max));
+        assert!(!hits_box(Vec3::Z * 5.0, Vec3::Z, 10.0, min, max));
+        assert!(!hits_box(
+            Vec3::new(2.0, 0.0, 5.0),
+            Vec3::NEG_Z,
+            10.0,
+            min,
+            max
+        ));
+        // parallel to a pair of faces, inside and outside of them
+        assert!(hits_box(Vec3::new(-5.0, 0.5, 0.5), Vec3::X, 10.0, min, max));
+        assert!(!hits_box(
+            Vec3::new(-5.0, 2.0, 0.5),
+            Vec3::X,
+            10.0,
+            min,
+            max
+        ));
+    }
+
+    /// A unit cube with the given transform
+    fn cube(
+        meshes: &mut Assets<Mesh>,
+        transform: Transform,
+    ) -> (Handle<Mesh>, GlobalTransform, Aabb) {
+        let mesh = Mesh::from(Cuboid::new(1.0, 1.0, 1.0));
+        let aabb = mesh.compute_aabb().unwrap();
+        (meshes.add(mesh), transform.into(), aabb)
+    }
+
+    fn cast(
+        meshes: &Assets<Mesh>,
+        targets: &[(Handle<Mesh>, GlobalTransform, Aabb)],
+        origin: Vec3,
+        max_distance: f32,
+    ) -> Option<f32> {
+        let ray = Ray3d::new(origin, Vec3::NEG_Z);
+        cast_ray(
+            ray,
+            max_distance,
+            meshes,
+            targets
+                .iter()
+                .map(|(mesh, transform, aabb)| (mesh, transform, aabb)),
+        )
+    }
+
+    #[test]
+    fn ray_hits_the_closest_mesh() {
+        let mut meshes = Assets::default();
+        let targets = [
+            cube(&mut meshes, Transform::from_xyz(0.0, 0.0, -10.0)),
+            cube(&mut meshes, Transform::IDENTITY),
+        ];
+        let hit = cast(&meshes, &targets, Vec3::Z * 5.0, 100.0).unwrap();
+        assert!((hit - 4.5).abs() < 1e-5, "{hit}");
+        assert_eq!(
+            cast(&meshes, &targets, Vec3::new(3.0, 0.0, 5.0), 100.0),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_hits_scaled_mesh_at_world_distance() {
+        let mut meshes = Assets::default();
+        let targets = [cube(&mut meshes, Transform::from_scale(Vec3::splat(4.0)))];
+        let hit = cast(&meshes, &targets, Vec3::Z * 5.0, 100.0).unwrap();
+        assert!((hit - 3.0).abs() < 1e-5, "{hit}");
+    }
+
+    #[test]
+    fn ray_stops_at_max_distance() {
+        let mut meshes = Assets::default();
+        let targets = [cube(&mut meshes, Transform::IDENTITY)];
+        assert_eq!(cast(&meshes, &targets, Vec3::Z * 5.0, 4.0), None);
+        assert!(cast(&meshes, &targets, Vec3::Z * 5.0, 5.0).is_some());
+    }
+}
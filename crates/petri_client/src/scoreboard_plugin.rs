@@ -0,0 +1,141 @@
+use bevy::prelude::*;
+use petri_shared::{Player, PlayerStats, Tint};
+
+use crate::{controls_plugin::Action, plugin::PetriState};
+
+pub struct ScoreboardPlugin;
+
+const SCOREBOARD_FONT_SIZE: f32 = 20.0;
+const COLUMNS: [&str; 4] = ["Name", "Ping", "Deaths", "Time"];
+
+#[derive(Component)]
+struct Scoreboard;
+
+#[derive(Component)]
+struct ScoreboardTable;
+
+impl Plugin for ScoreboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(PetriState::Scene), spawn_scoreboard)
+            .add_systems(
+                Update,
+                (toggle_scoreboard, update_scoreboard)
+                    .chain()
+                    .run_if(in_state(PetriState::Scene)),
+            )
+            .add_systems(
+                OnExit(PetriState::Scene),
+                |mut cmd: Commands, scoreboard: Query<Entity, With<Scoreboard>>| {
+                    scoreboard
+                        .iter()
+                        .for_each(|e| cmd.entity(e).despawn_recursive())
+                },
+            );
+    }
+}
+
+fn spawn_scoreboard(mut cmd: Commands) {
+    cmd.spawn((
+        NodeBundle {
+            style: Style {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        },
+        Scoreboard,
+    ))
+    .with_children(|parent| {
+        parent.spawn((
+            NodeBundle {
+                style: Style {
+                    display: Display::Grid,
+                    grid_template_columns: vec![
+                        GridTrack::min_content(),
+                        GridTrack::auto(),
+                        GridTrack::auto(),
+                        GridTrack::auto(),
+                    ],
+                    column_gap: Val::Px(30.0),
+                    row_gap: Val::Px(4.0),
+                    padding: UiRect::all(Val::Px(20.0)),
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+                ..default()
+            },
+            ScoreboardTable,
+        ));
+    });
+}
+
+/// The scoreboard is only shown while [`Action::Scoreboard`] is held, Tab by default
+fn toggle_scoreboard(
+    actions: Res<ButtonInput<Action>>,
+    mut scoreboard: Query<&mut Style, With<Scoreboard>>,
+) {
+    let display = if actions.pressed(Action::Scoreboard) {
+        Display::Flex
+    } else {
+        Display::None
+    };
+    for mut style in &mut scoreboard {
+        if style.display != display {
+            style.display = display;
+        }
+    }
+}
+
+fn update_scoreboard(
+    mut cmd: Commands,
+    scoreboard: Query<Ref<Style>, With<Scoreboard>>,
+    table: Query<Entity, With<ScoreboardTable>>,
+    players: Query<(Option<&Name>, &Tint, &PlayerStats), With<Player>>,
+    // stats are refreshed every second, so this also picks up renames
+    changed_players: Query<(), (With<Player>, Changed<PlayerStats>)>,
+    asset_server: Res<AssetServer>,
+) {
+    let (Ok(style), Ok(table)) = (scoreboard.get_single(), table.get_single()) else {
+        return;
+    };
+    if style.display == Display::None || (!style.is_changed() && changed_players.is_empty()) {
+        return;
+    }
+
+    let mut rows: Vec<_> = players.iter().collect();
+    rows.sort_by_key(|(name, ..)| name.map(|n| n.to_lowercase()));
+
+    let font = asset_server.load("open-sans.ttf");
+    let cell = |value: String, color: Color| {
+        TextBundle::from_section(
+            value,
+            TextStyle {
+                font: font.clone(),
+                font_size: SCOREBOARD_FONT_SIZE,
+                color,
+            },
+        )
+    };
+
+    cmd.entity(table)
+        .despawn_descendants()
+        .with_children(|parent| {
+            for header in COLUMNS {
+                parent.spawn(cell(header.to_owned(), Color::GRAY));
+            }
+            for (name, tint, stats) in rows {
+                let name = name.map_or("...", |n| n.as_str());
+                let minutes = stats.connected_secs / 60;
+                let seconds = stats.connected_secs % 60;
+                parent.spawn(cell(name.to_owned(), tint.0));
+                parent.spawn(cell(format!("{} ms", stats.ping_ms), Color::WHITE));
+                parent.spawn(cell(stats.deaths.to_string(), Color::WHITE));
+                parent.spawn(cell(format!("{minutes}:{seconds:02}"), Color::WHITE));
+            }
+        });
+}
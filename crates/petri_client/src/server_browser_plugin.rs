@@ -0,0 +1,586 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+    time::common_conditions::on_timer,
+    utils::{HashMap, HashSet},
+};
+use petri_shared::{
+    discovery::{
+        receive_datagrams, DiscoveryRequest, DiscoveryResponse, ServerInfo, DISCOVERY_PORT,
+    },
+    registry::{registry_address, RegistryRequest, RegistryResponse},
+    PROTOCOL_VERSION,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{config_path, load_config, save_config},
+    plugin::PetriState,
+};
+
+/// Lists the favourite servers, the ones found on the LAN and the ones from the registry,
+/// and lets the player pick one to connect to
+pub struct ServerBrowserPlugin;
+
+impl Plugin for ServerBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerList>()
+            .add_systems(
+                OnEnter(PetriState::ServerBrowser),
+                (
+                    (open_discovery_socket, load_servers, send_discovery_requests).chain(),
+                    spawn_browser_screen,
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    send_discovery_requests.run_if(on_timer(REFRESH_INTERVAL)),
+                    receive_discovery_responses,
+                    add_resolved_servers,
+                    browser_input,
+                    update_browser_screen,
+                )
+                    .chain()
+                    .run_if(in_state(PetriState::ServerBrowser)),
+            )
+            .add_systems(
+                OnExit(PetriState::ServerBrowser),
+                |mut cmd: Commands, ui: Query<Entity, With<BrowserUIMarker>>| {
+                    ui.iter().for_each(|e| cmd.entity(e).despawn_recursive());
+                    cmd.remove_resource::<DiscoverySocket>();
+                },
+            );
+    }
+}
+
+/// Port of the game server when it's not known from discovery
+pub const DEFAULT_GAME_PORT: u16 = 8989;
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+/// Responses to requests older than this are ignored
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The server picked in the browser, used by [`crate::connecting_plugin`]
+#[derive(Resource, Debug, Clone)]
+pub struct SelectedServer {
+    pub host: String,
+    pub port: u16,
+}
+
+impl std::str::FromStr for SelectedServer {
+    type Err = anyhow::Error;
+
+    /// Parses `host`, `host:port`, an IP address or `[ipv6]:port`
+    fn from_str(address: &str) -> anyhow::Result<Self> {
+        if let Ok(address) = address.parse::<SocketAddr>() {
+            return Ok(Self {
+                host: address.ip().to_string(),
+                port: address.port(),
+            });
+        }
+        if let Ok(ip) = address.parse::<IpAddr>() {
+            return Ok(Self {
+                host: ip.to_string(),
+                port: DEFAULT_GAME_PORT,
+            });
+        }
+        let (host, port) = match address.split_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .with_context(|| format!("{port:?} is not a valid port"))?,
+            ),
+            None => (address, DEFAULT_GAME_PORT),
+        };
+        anyhow::ensure!(!host.is_empty(), "The server address has no host");
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Favourite {
+    name: String,
+    host: String,
+}
+
+#[derive(Debug)]
+struct ServerEntry {
+    host: String,
+    favourite: Option<Favourite>,
+    /// Where discovery requests for this server go, unknown if the host didn't resolve
+    discovery_addr: Option<SocketAddr>,
+    info: Option<ServerInfo>,
+    ping: Option<Duration>,
+}
+
+impl ServerEntry {
+    fn new(host: String, favourite: Option<Favourite>) -> Self {
+        let ip = resolve(&host).inspect_err(|e| warn!("{e:#}")).ok();
+        Self::resolved(host, ip, favourite)
+    }
+
+    fn resolved(host: String, ip: Option<IpAddr>, favourite: Option<Favourite>) -> Self {
+        Self {
+            host,
+            favourite,
+            discovery_addr: ip.map(|ip| SocketAddr::new(ip, DISCOVERY_PORT)),
+            info: None,
+            ping: None,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match (&self.favourite, &self.info) {
+            (_, Some(info)) => &info.name,
+            (Some(favourite), None) => &favourite.name,
+            (None, None) => &self.host,
+        }
+    }
+
+    fn is_compatible(&self) -> bool {
+        self.info
+            .as_ref()
+            .map_or(true, |info| info.protocol_version == PROTOCOL_VERSION)
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+struct ServerList {
+    entries: Vec<ServerEntry>,
+    selected: usize,
+    /// Send times of the recent discovery requests by nonce
+    pending: HashMap<u64, Instant>,
+    next_nonce: u64,
+    /// Shown under the list, e.g. when the favourites could not be saved
+    status: Option<String>,
+    /// See [`petri_shared::registry`]
+    registry: Option<SocketAddr>,
+    /// Registered hosts that resolved to a server already on the list
+    merged_hosts: HashSet<String>,
+    /// Registered hosts being looked up off the main thread, see [`add_resolved_servers`]
+    resolving: HashMap<String, (ServerInfo, Task<Option<IpAddr>>)>,
+}
+
+#[derive(Resource)]
+struct DiscoverySocket(UdpSocket);
+
+#[derive(Component)]
+struct BrowserUIMarker;
+
+#[derive(Component)]
+struct ServerListText;
+
+fn favourites_path() -> PathBuf {
+    config_path("PETRI_FAVOURITES", "favourites.ron")
+}
+
+fn load_favourites() -> anyhow::Result<Vec<Favourite>> {
+    Ok(load_config(&favourites_path())?.unwrap_or_default())
+}
+
+fn save_favourites(favourites: &[Favourite]) -> anyhow::Result<()> {
+    save_config(&favourites_path(), &favourites)
+}
+
+/// Blocks until the lookup is done, so only for hosts that aren't known yet.
+/// Prefers IPv4: the discovery socket and the servers are IPv4,
+/// and `localhost` may resolve to `::1` first.
+pub(crate) fn resolve(host: &str) -> anyhow::Result<IpAddr> {
+    if let Ok(ip) = host.parse() {
+        return Ok(ip);
+    }
+    let addrs = dns_lookup::lookup_host(host).with_context(|| format!("Could not find {host}"))?;
+    addrs
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .or(addrs.first())
+        .copied()
+        .with_context(|| format!("{host} has no addresses"))
+}
+
+fn open_discovery_socket(mut commands: Commands) {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).and_then(|socket| {
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    });
+    match socket {
+        Ok(socket) => commands.insert_resource(DiscoverySocket(socket)),
+        Err(e) => error!("Could not open the discovery socket: {e}"),
+    }
+}
+
+/// Fills the list with the favourites and the server given on the command line
+fn load_servers(mut list: ResMut<ServerList>, selected: Option<Res<SelectedServer>>) {
+    let registry = registry_address();
+    // the discovery socket is IPv4, and `localhost` may resolve to `::1` first
+    list.registry = match registry.to_socket_addrs() {
+        Ok(mut addrs) => addrs.find(SocketAddr::is_ipv4),
+        Err(e) => {
+            warn!("Could not find the registry at {registry}: {e}");
+            None
+        }
+    };
+
+    let favourites = load_favourites().unwrap_or_else(|e| {
+        error!("{e:?}");
+        list.status = Some(format!("{e:#}"));
+        Vec::new()
+    });
+    list.merged_hosts.clear();
+    list.resolving.clear();
+    list.entries = favourites
+        .into_iter()
+        .map(|f| ServerEntry::new(f.host.clone(), Some(f)))
+        .collect();
+    if let Some(host) = std::env::args().nth(1) {
+        if !list.entries.iter().any(|e| e.host == host) {
+            list.entries.push(ServerEntry::new(host, None));
+        }
+    }
+    list.selected = selected
+        .and_then(|s| list.entries.iter().position(|e| e.host == s.host))
+        .unwrap_or(0);
+}
+
+/// Pings the known servers and asks the LAN and the registry for more
+fn send_discovery_requests(mut list: ResMut<ServerList>, socket: Option<Res<DiscoverySocket>>) {
+    let Some(socket) = socket else {
+        return;
+    };
+    let now = Instant::now();
+    list.pending.retain(|_, sent| now - *sent < REQUEST_TIMEOUT);
+    if list.next_nonce == 0 {
+        // don't let responses to a previous run of the client match the new requests
+        list.next_nonce = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(1, |t| t.as_nanos() as u64);
+    }
+    let nonce = list.next_nonce;
+    list.next_nonce = list.next_nonce.wrapping_add(1);
+    list.pending.insert(nonce, now);
+
+    let request = DiscoveryRequest { nonce }.to_bytes();
+    let broadcast = [
+        SocketAddr::new(Ipv4Addr::BROADCAST.into(), DISCOVERY_PORT),
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DISCOVERY_PORT),
+    ];
+    let known = list.entries.iter().filter_map(|e| e.discovery_addr);
+    for addr in broadcast.into_iter().chain(known) {
+        if let Err(e) = socket.0.send_to(&request, addr) {
+            debug!("Could not send a discovery request to {addr}: {e}");
+        }
+    }
+
+    if let Some(registry) = list.registry {
+        let request = RegistryRequest::List { nonce, page: 0 };
+        if let Err(e) = socket.0.send_to(&request.to_bytes(), registry) {
+            debug!("Could not reach the registry at {registry}: {e}");
+        }
+    }
+}
+
+fn receive_discovery_responses(mut list: ResMut<ServerList>, socket: Option<Res<DiscoverySocket>>) {
+    let Some(socket) = socket else {
+        return;
+    };
+    let mut buf = [0; 1024];
+    let received = receive_datagrams(&socket.0, &mut buf, |bytes, from| {
+        if let Some(response) = RegistryResponse::from_bytes(bytes) {
+            if Some(from) == list.registry && list.pending.contains_key(&response.nonce) {
+                add_registered_servers(&mut list, &socket.0, response);
+            }
+            return;
+        }
+        let Some(response) = DiscoveryResponse::from_bytes(bytes) else {
+            debug!("Ignoring a malformed discovery response from {from}");
+            return;
+        };
+        let Some(sent) = list.pending.get(&response.nonce) else {
+            return;
+        };
+        let ping = sent.elapsed();
+
+        let entry = match list
+            .entries
+            .iter()
+            .position(|e| e.discovery_addr == Some(from))
+        {
+            Some(i) => &mut list.entries[i],
+            None => {
+                list.entries.push(ServerEntry {
+                    host: from.ip().to_string(),
+                    favourite: None,
+                    discovery_addr: Some(from),
+                    info: None,
+                    ping: None,
+                });
+                list.entries.last_mut().unwrap()
+            }
+        };
+        entry.ping = Some(ping);
+        entry.info = Some(response.info);
+    });
+    if let Err(e) = received {
+        debug!("Could not receive discovery responses: {e}");
+    }
+}
+
+/// Starts looking up the servers from the registry and asks for the next page.
+/// Their ping is measured by the next discovery requests sent to them directly.
+fn add_registered_servers(list: &mut ServerList, socket: &UdpSocket, response: RegistryResponse) {
+    for server in response.servers {
+        // every refresh lists the same servers again
+        if list.merged_hosts.contains(&server.host) || list.resolving.contains_key(&server.host) {
+            continue;
+        }
+        if let Some(known) = list.entries.iter_mut().find(|e| e.host == server.host) {
+            known.info.get_or_insert(server.info);
+            continue;
+        }
+        // the registry decides how many hosts there are and how slow they are to look up
+        let host = server.host.clone();
+        let task = AsyncComputeTaskPool::get()
+            .spawn(async move { resolve(&host).inspect_err(|e| warn!("{e:#}")).ok() });
+        list.resolving.insert(server.host, (server.info, task));
+    }
+
+    if response.page + 1 < response.page_count {
+        let request = RegistryRequest::List {
+            nonce: response.nonce,
+            page: response.page + 1,
+        };
+        if let Some(registry) = list.registry {
+            if let Err(e) = socket.send_to(&request.to_bytes(), registry) {
+                debug!("Could not ask the registry for more servers: {e}");
+            }
+        }
+    }
+}
+
+/// Lists the registered servers that have been looked up,
+/// unless they turn out to be a server that is already on the list
+fn add_resolved_servers(mut list: ResMut<ServerList>) {
+    let resolved: Vec<_> = list
+        .resolving
+        .iter_mut()
+        .filter_map(|(host, (_, task))| {
+            block_on(future::poll_once(task)).map(|ip| (host.clone(), ip))
+        })
+        .collect();
+    for (host, ip) in resolved {
+        let Some((info, _)) = list.resolving.remove(&host) else {
+            continue;
+        };
+        let entry = ServerEntry::resolved(host, ip, None);
+        let known = list
+            .entries
+            .iter_mut()
+            .find(|e| entry.discovery_addr.is_some() && e.discovery_addr == entry.discovery_addr);
+        match known {
+            Some(known) => {
+                known.info.get_or_insert(info);
+                list.merged_hosts.insert(entry.host);
+            }
+            None => list.entries.push(ServerEntry {
+                info: Some(info),
+                ..entry
+            }),
+        }
+    }
+}
+
+/// Up and Down pick a server, Enter connects to it, F adds or removes it from the favourites,
+/// R refreshes the list and Escape goes back to login
+fn browser_input(
+    mut commands: Commands,
+    key: Res<ButtonInput<KeyCode>>,
+    mut list: ResMut<ServerList>,
+    socket: Option<Res<DiscoverySocket>>,
+    mut next_state: ResMut<NextState<PetriState>>,
+) {
+    if key.just_pressed(KeyCode::Escape) {
+        next_state.set(PetriState::Login);
+        return;
+    }
+    if key.just_pressed(KeyCode::KeyR) {
+        for entry in &mut list.entries {
+            entry.info = None;
+            entry.ping = None;
+        }
+        // forget the servers that were only found on the LAN or the registry,
+        // they'll answer again if still up
+        list.entries.retain(|e| e.favourite.is_some());
+        list.merged_hosts.clear();
+        list.resolving.clear();
+        list.selected = 0;
+        send_discovery_requests(list, socket);
+        return;
+    }
+
+    let count = list.entries.len();
+    if count == 0 {
+        return;
+    }
+    if key.just_pressed(KeyCode::ArrowUp) {
+        list.selected = (list.selected + count - 1) % count;
+    }
+    if key.just_pressed(KeyCode::ArrowDown) {
+        list.selected = (list.selected + 1) % count;
+    }
+    let selected = list.selected.min(count - 1);
+
+    if key.just_pressed(KeyCode::KeyF) {
+        let entry = &mut list.entries[selected];
+        entry.favourite = match entry.favourite {
+            Some(_) => None,
+            None => Some(Favourite {
+                name: entry.name().to_owned(),
+                host: entry.host.clone(),
+            }),
+        };
+        let favourites: Vec<_> = list
+            .entries
+            .iter()
+            .filter_map(|e| e.favourite.clone())
+            .collect();
+        list.status = save_favourites(&favourites).err().map(|e| {
+            error!("{e:?}");
+            format!("{e:#}")
+        });
+    }
+
+    if key.just_pressed(KeyCode::Enter) {
+        let entry = &list.entries[selected];
+        if !entry.is_compatible() {
+            list.status = Some(format!("{} runs a different version", entry.name()));
+            return;
+        }
+        commands.insert_resource(SelectedServer {
+            host: entry.host.clone(),
+            port: entry
+                .info
+                .as_ref()
+                .map_or(DEFAULT_GAME_PORT, |info| info.game_port),
+        });
+        next_state.set(PetriState::Connecting);
+    }
+}
+
+fn spawn_browser_screen(mut cmd: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("open-sans.ttf"),
+        font_size: 24.0,
+        ..default()
+    };
+    cmd.spawn((Camera2dBundle::default(), BrowserUIMarker));
+    cmd.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(20.0),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::DARK_GRAY),
+            ..default()
+        },
+        BrowserUIMarker,
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section(
+            "Servers",
+            TextStyle {
+                font_size: 60.0,
+                ..text_style.clone()
+            },
+        ));
+        parent.spawn((
+            TextBundle::from_section("", text_style.clone()),
+            ServerListText,
+        ));
+        parent.spawn(TextBundle::from_section(
+            "Up/Down to select, Enter to connect, F to add or remove a favourite, \
+             R to refresh, Escape to go back",
+            TextStyle {
+                font_size: 18.0,
+                ..text_style
+            },
+        ));
+    });
+}
+
+fn update_browser_screen(list: Res<ServerList>, mut text: Query<&mut Text, With<ServerListText>>) {
+    if !list.is_changed() {
+        return;
+    }
+    let Ok(mut text) = text.get_single_mut() else {
+        return;
+    };
+    let style = text.sections[0].style.clone();
+
+    let mut sections = Vec::new();
+    if list.entries.is_empty() {
+        sections.push(TextSection::new(
+            "Looking for servers...\n\
+             Servers on other machines are only found if they were started with --lan\n",
+            style.clone(),
+        ));
+    }
+    for (i, entry) in list.entries.iter().enumerate() {
+        let star = if entry.favourite.is_some() { "*" } else { " " };
+        let details = match (&entry.info, entry.ping) {
+            (Some(info), ping) => format!(
+                "{}/{}  {}  {} ms{}",
+                info.players,
+                info.max_clients,
+                info.map,
+                ping.map_or(0, |p| p.as_millis()),
+                if entry.is_compatible() {
+                    ""
+                } else {
+                    "  (different version)"
+                },
+            ),
+            (None, _) if entry.discovery_addr.is_none() => "not found".to_owned(),
+            (None, _) => "no response".to_owned(),
+        };
+        let color = if i == list.selected {
+            Color::YELLOW
+        } else if entry.info.is_none() || !entry.is_compatible() {
+            Color::GRAY
+        } else {
+            Color::WHITE
+        };
+        sections.push(TextSection::new(
+            format!("{star} {}  ({})  {details}\n", entry.name(), entry.host),
+            TextStyle {
+                color,
+                ..style.clone()
+            },
+        ));
+    }
+    if let Some(status) = &list.status {
+        sections.push(TextSection::new(
+            format!("\n{status}"),
+            TextStyle {
+                color: Color::ORANGE_RED,
+                ..style
+            },
+        ));
+    }
+    text.sections = sections;
+}
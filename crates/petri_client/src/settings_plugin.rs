@@ -0,0 +1,359 @@
+use std::path::PathBuf;
+
+use bevy::{
+    audio::Volume,
+    pbr::DirectionalLightShadowMap,
+    prelude::*,
+    window::{PresentMode, PrimaryWindow, WindowMode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{config_path, load_config, save_config},
+    plugin::PetriState,
+};
+
+/// Loads, applies and edits the player's [`Settings`]
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        let settings = Settings::load().unwrap_or_else(|e| {
+            error!("{e:?}, using the default settings");
+            default()
+        });
+        app.insert_resource(settings)
+            .init_resource::<SelectedSetting>()
+            .add_systems(
+                Update,
+                (
+                    apply_window_settings.run_if(resource_changed::<Settings>),
+                    apply_graphics_settings,
+                ),
+            )
+            .add_systems(OnEnter(PetriState::Settings), spawn_settings_screen)
+            .add_systems(
+                Update,
+                (settings_input, update_settings_screen)
+                    .chain()
+                    .run_if(in_state(PetriState::Settings)),
+            )
+            .add_systems(
+                OnExit(PetriState::Settings),
+                (save_settings, despawn_settings_screen),
+            );
+    }
+}
+
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Radians per pixel of mouse movement
+    pub mouse_sensitivity: f32,
+    pub invert_y: bool,
+    /// How much of the way the camera has left to turn after each 1/60 s, 0 is off
+    pub mouse_smoothing: f32,
+    /// Vertical field of view
+    pub fov_degrees: f32,
+    /// From 0 to 1
+    pub volume: f32,
+    pub window_mode: WindowMode,
+    pub vsync: bool,
+    pub quality: Quality,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mouse_sensitivity: 0.001,
+            invert_y: false,
+            mouse_smoothing: 0.0,
+            fov_degrees: 45.0,
+            volume: 1.0,
+            window_mode: WindowMode::Windowed,
+            vsync: false,
+            quality: Quality::Medium,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Quality {
+    Low,
+    Medium,
+    High,
+}
+
+impl Quality {
+    fn msaa(self) -> Msaa {
+        match self {
+            Quality::Low => Msaa::Off,
+            Quality::Medium => Msaa::Sample4,
+            Quality::High => Msaa::Sample8,
+        }
+    }
+
+    /// Lights from the level have no shadows unless the quality is high
+    fn shadows(self) -> bool {
+        self == Quality::High
+    }
+
+    fn shadow_map_size(self) -> usize {
+        match self {
+            Quality::Low => 1024,
+            Quality::Medium => 2048,
+            Quality::High => 4096,
+        }
+    }
+}
+
+fn settings_path() -> PathBuf {
+    config_path("PETRI_SETTINGS", "settings.ron")
+}
+
+impl Settings {
+    fn load() -> anyhow::Result<Self> {
+        Ok(load_config(&settings_path())?.unwrap_or_default())
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        save_config(&settings_path(), self)
+    }
+}
+
+fn apply_window_settings(
+    settings: Res<Settings>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let present_mode = match settings.vsync {
+        true => PresentMode::AutoVsync,
+        false => PresentMode::AutoNoVsync,
+    };
+    for mut window in &mut windows {
+        // only touch the window when needed, changing it recreates the surface
+        if window.present_mode != present_mode {
+            window.present_mode = present_mode;
+        }
+        if window.mode != settings.window_mode {
+            window.mode = settings.window_mode;
+        }
+    }
+}
+
+/// Also applies to cameras and lights spawned after the settings changed
+fn apply_graphics_settings(
+    settings: Res<Settings>,
+    mut msaa: ResMut<Msaa>,
+    mut shadow_map: ResMut<DirectionalLightShadowMap>,
+    mut volume: ResMut<GlobalVolume>,
+    mut projections: Query<&mut Projection>,
+    mut lights: Query<&mut DirectionalLight>,
+) {
+    if settings.is_changed() {
+        msaa.set_if_neq(settings.quality.msaa());
+        shadow_map.size = settings.quality.shadow_map_size();
+        volume.volume = Volume::new(settings.volume);
+    }
+    for mut projection in &mut projections {
+        if !settings.is_changed() && !projection.is_added() {
+            continue;
+        }
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = settings.fov_degrees.to_radians();
+        }
+    }
+    for mut light in &mut lights {
+        if settings.is_changed() || light.is_added() {
+            light.shadows_enabled = settings.quality.shadows();
+        }
+    }
+}
+
+/// Which row of the settings screen is selected
+#[derive(Resource, Default, Debug)]
+struct SelectedSetting(usize);
+
+#[derive(Component)]
+struct SettingsUIMarker;
+
+#[derive(Component)]
+struct SettingsText;
+
+/// Rows of the settings screen, each can be changed one step at a time
+const SETTING_NAMES: [&str; 8] = [
+    "Mouse sensitivity",
+    "Invert Y",
+    "Mouse smoothing",
+    "Field of view",
+    "Volume",
+    "Window mode",
+    "VSync",
+    "Graphics quality",
+];
+
+const WINDOW_MODES: [WindowMode; 3] = [
+    WindowMode::Windowed,
+    WindowMode::BorderlessFullscreen,
+    WindowMode::Fullscreen,
+];
+const QUALITIES: [Quality; 3] = [Quality::Low, Quality::Medium, Quality::High];
+
+/// Picks the next or previous item of `items`, wrapping around
+fn cycle<T: PartialEq + Copy>(items: &[T], current: T, step: i32) -> T {
+    let index = items.iter().position(|i| *i == current).unwrap_or(0) as i32;
+    items[(index + step).rem_euclid(items.len() as i32) as usize]
+}
+
+impl Settings {
+    fn step(&mut self, setting: usize, step: i32) {
+        let step_f = step as f32;
+        match setting {
+            0 => {
+                self.mouse_sensitivity =
+                    (self.mouse_sensitivity + step_f * 0.0001).clamp(0.0001, 0.01)
+            }
+            1 => self.invert_y = !self.invert_y,
+            2 => self.mouse_smoothing = (self.mouse_smoothing + step_f * 0.1).clamp(0.0, 0.9),
+            3 => self.fov_degrees = (self.fov_degrees + step_f * 5.0).clamp(30.0, 100.0),
+            4 => self.volume = (self.volume + step_f * 0.1).clamp(0.0, 1.0),
+            5 => self.window_mode = cycle(&WINDOW_MODES, self.window_mode, step),
+            6 => self.vsync = !self.vsync,
+            7 => self.quality = cycle(&QUALITIES, self.quality, step),
+            _ => {}
+        }
+    }
+
+    fn describe(&self, setting: usize) -> String {
+        let on_off = |on| if on { "On" } else { "Off" }.to_owned();
+        match setting {
+            0 => format!("{:.1}", self.mouse_sensitivity * 1000.0),
+            1 => on_off(self.invert_y),
+            2 => match self.mouse_smoothing {
+                s if s < 0.05 => "Off".to_owned(),
+                s => format!("{:.0}%", s * 100.0),
+            },
+            3 => format!("{:.0}°", self.fov_degrees),
+            4 => format!("{:.0}%", self.volume * 100.0),
+            5 => match self.window_mode {
+                WindowMode::Windowed => "Windowed",
+                WindowMode::BorderlessFullscreen => "Borderless",
+                WindowMode::Fullscreen | WindowMode::SizedFullscreen => "Fullscreen",
+            }
+            .to_owned(),
+            6 => on_off(self.vsync),
+            7 => format!("{:?}", self.quality),
+            _ => String::new(),
+        }
+    }
+}
+
+fn spawn_settings_screen(mut cmd: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("open-sans.ttf"),
+        font_size: 30.0,
+        ..default()
+    };
+    cmd.spawn((Camera2dBundle::default(), SettingsUIMarker));
+    cmd.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(20.0),
+                ..default()
+            },
+            background_color: BackgroundColor(Color::DARK_GRAY),
+            ..default()
+        },
+        SettingsUIMarker,
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section(
+            "Settings",
+            TextStyle {
+                font_size: 60.0,
+                ..text_style.clone()
+            },
+        ));
+        parent.spawn((
+            TextBundle::from_section("", text_style.clone()),
+            SettingsText,
+        ));
+        parent.spawn(TextBundle::from_section(
+            "Up/Down to select, Left/Right to change, Escape to go back",
+            TextStyle {
+                font_size: 18.0,
+                ..text_style
+            },
+        ));
+    });
+}
+
+fn despawn_settings_screen(mut cmd: Commands, ui: Query<Entity, With<SettingsUIMarker>>) {
+    ui.iter().for_each(|e| cmd.entity(e).despawn_recursive());
+}
+
+fn settings_input(
+    key: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<Settings>,
+    mut selected: ResMut<SelectedSetting>,
+    mut next_state: ResMut<NextState<PetriState>>,
+) {
+    let count = SETTING_NAMES.len();
+    if key.just_pressed(KeyCode::ArrowUp) {
+        selected.0 = (selected.0 + count - 1) % count;
+    }
+    if key.just_pressed(KeyCode::ArrowDown) {
+        selected.0 = (selected.0 + 1) % count;
+    }
+    if key.just_pressed(KeyCode::ArrowLeft) {
+        settings.step(selected.0, -1);
+    }
+    if key.just_pressed(KeyCode::ArrowRight) {
+        settings.step(selected.0, 1);
+    }
+    if key.just_pressed(KeyCode::Escape) {
+        next_state.set(PetriState::MainMenu);
+    }
+}
+
+fn update_settings_screen(
+    settings: Res<Settings>,
+    selected: Res<SelectedSetting>,
+    mut text: Query<(&mut Text, Ref<SettingsText>)>,
+) {
+    let Ok((mut text, marker)) = text.get_single_mut() else {
+        return;
+    };
+    if !settings.is_changed() && !selected.is_changed() && !marker.is_added() {
+        return;
+    }
+    let style = text.sections[0].style.clone();
+    text.sections = SETTING_NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let color = if i == selected.0 {
+                Color::YELLOW
+            } else {
+                Color::WHITE
+            };
+            TextSection::new(
+                format!("{name}: {}\n", settings.describe(i)),
+                TextStyle {
+                    color,
+                    ..style.clone()
+                },
+            )
+        })
+        .collect();
+}
+
+fn save_settings(settings: Res<Settings>) {
+    if let Err(e) = settings.save() {
+        error!("{e:?}");
+    }
+}
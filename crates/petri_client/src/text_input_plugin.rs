@@ -0,0 +1,413 @@
+use std::ops::Range;
+
+use bevy::{
+    input::{keyboard::KeyboardInput, ButtonState},
+    prelude::*,
+};
+
+/// Single line text fields, see [`TextInput`]
+pub struct TextInputPlugin;
+
+impl Plugin for TextInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_non_send_resource::<SystemClipboard>().add_systems(
+            Update,
+            (edit_focused_input, render_text_inputs)
+                .chain()
+                .in_set(TextInputSet),
+        );
+    }
+}
+
+/// Systems that edit and render [`TextInput`]s,
+/// Enter, Tab and Escape are left for the screen the input is on
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TextInputSet;
+
+const TEXT_COLOR: Color = Color::WHITE;
+const PLACEHOLDER_COLOR: Color = Color::GRAY;
+const SELECTION_COLOR: Color = Color::CYAN;
+const CURSOR: &str = "|";
+
+/// Text field to put on an entity with a [`Text`], which shows the value,
+/// the cursor and the selection. Only the focused input receives keyboard input.
+#[derive(Component, Debug, Clone)]
+pub struct TextInput {
+    value: String,
+    /// Byte index in `value`
+    cursor: usize,
+    /// Where the selection started, it goes from here to the cursor
+    anchor: Option<usize>,
+    /// In characters
+    pub max_len: usize,
+    pub allowed: fn(char) -> bool,
+    /// Shown when the value is empty
+    pub placeholder: String,
+    pub focused: bool,
+}
+
+impl TextInput {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            value: String::new(),
+            cursor: 0,
+            anchor: None,
+            max_len,
+            allowed: |_| true,
+            placeholder: String::new(),
+            focused: false,
+        }
+    }
+
+    pub fn with_allowed(mut self, allowed: fn(char) -> bool) -> Self {
+        self.allowed = allowed;
+        self
+    }
+
+    pub fn with_placeholder(mut self, placeholder: impl Into<String>) -> Self {
+        self.placeholder = placeholder.into();
+        self
+    }
+
+    pub fn with_value(mut self, value: &str) -> Self {
+        self.set_value(value);
+        self
+    }
+
+    pub fn focused(mut self) -> Self {
+        self.focused = true;
+        self
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Replaces the value, dropping characters that aren't allowed or don't fit
+    pub fn set_value(&mut self, value: &str) {
+        self.value.clear();
+        self.cursor = 0;
+        self.anchor = None;
+        self.insert(value);
+    }
+
+    /// Types `text` at the cursor, replacing the selection
+    pub fn insert(&mut self, text: &str) {
+        self.delete_selection();
+        let room = self.max_len.saturating_sub(self.value.chars().count());
+        let text: String = text
+            .chars()
+            .filter(|c| !c.is_control() && (self.allowed)(*c))
+            .take(room)
+            .collect();
+        self.value.insert_str(self.cursor, &text);
+        self.cursor += text.len();
+    }
+
+    fn selection(&self) -> Option<Range<usize>> {
+        let anchor = self.anchor.filter(|a| *a != self.cursor)?;
+        Some(anchor.min(self.cursor)..anchor.max(self.cursor))
+    }
+
+    fn selected_text(&self) -> Option<&str> {
+        self.selection().map(|range| &self.value[range])
+    }
+
+    /// Returns false if nothing was selected
+    fn delete_selection(&mut self) -> bool {
+        let Some(range) = self.selection() else {
+            return false;
+        };
+        self.cursor = range.start;
+        self.anchor = None;
+        self.value.drain(range);
+        true
+    }
+
+    fn select_all(&mut self) {
+        self.anchor = Some(0);
+        self.cursor = self.value.len();
+    }
+
+    /// Moves the cursor, extending the selection or dropping it
+    fn move_to(&mut self, position: usize, select: bool) {
+        if select {
+            self.anchor.get_or_insert(self.cursor);
+        } else {
+            self.anchor = None;
+        }
+        self.cursor = position;
+    }
+
+    /// Position of the previous character, or the start of the previous word
+    fn previous(&self, word: bool) -> usize {
+        let mut chars = self.value[..self.cursor].char_indices().rev().peekable();
+        if !word {
+            return chars.next().map_or(0, |(i, _)| i);
+        }
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        let mut start = chars.peek().map_or(0, |(i, _)| *i);
+        while let Some((i, _)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+            start = i;
+        }
+        start
+    }
+
+    /// Position after the next character, or after the end of the next word
+    fn next(&self, word: bool) -> usize {
+        let rest = &self.value[self.cursor..];
+        let mut chars = rest.char_indices().peekable();
+        if !word {
+            chars.next();
+        } else {
+            while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+            while chars.next_if(|(_, c)| !c.is_whitespace()).is_some() {}
+        }
+        self.cursor + chars.peek().map_or(rest.len(), |(i, _)| *i)
+    }
+
+    fn backspace(&mut self, word: bool) {
+        if !self.delete_selection() {
+            let start = self.previous(word);
+            self.value.drain(start..self.cursor);
+            self.cursor = start;
+        }
+    }
+
+    fn delete(&mut self, word: bool) {
+        if !self.delete_selection() {
+            let end = self.next(word);
+            self.value.drain(self.cursor..end);
+        }
+    }
+}
+
+/// The system clipboard, opened when first used.
+/// On some platforms copied text is lost when it's closed, so it's kept open.
+#[derive(Default)]
+struct SystemClipboard(Option<arboard::Clipboard>);
+
+impl SystemClipboard {
+    fn get(&mut self) -> Option<&mut arboard::Clipboard> {
+        if self.0.is_none() {
+            match arboard::Clipboard::new() {
+                Ok(clipboard) => self.0 = Some(clipboard),
+                Err(e) => warn!("Could not open the clipboard: {e}"),
+            }
+        }
+        self.0.as_mut()
+    }
+}
+
+/// Arrows and Home/End move the cursor, with Shift they select and with Ctrl they skip words.
+/// Ctrl+A selects everything, Ctrl+C, Ctrl+X and Ctrl+V use the clipboard.
+fn edit_focused_input(
+    mut keyboard_input_events: EventReader<KeyboardInput>,
+    mut char_input_events: EventReader<ReceivedCharacter>,
+    key: Res<ButtonInput<KeyCode>>,
+    mut inputs: Query<&mut TextInput>,
+    mut clipboard: NonSendMut<SystemClipboard>,
+) {
+    let Some(mut input) = inputs.iter_mut().find(|i| i.focused) else {
+        keyboard_input_events.clear();
+        char_input_events.clear();
+        return;
+    };
+    let shift = key.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let ctrl = key.any_pressed([
+        KeyCode::ControlLeft,
+        KeyCode::ControlRight,
+        KeyCode::SuperLeft,
+        KeyCode::SuperRight,
+    ]);
+
+    // AltGr is Ctrl+Alt on some platforms, so only the shortcuts themselves aren't typing
+    let mut shortcut = false;
+    for event in keyboard_input_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match event.key_code {
+            KeyCode::ArrowLeft => match input.selection() {
+                Some(range) if !shift => input.move_to(range.start, false),
+                _ => {
+                    let position = input.previous(ctrl);
+                    input.move_to(position, shift);
+                }
+            },
+            KeyCode::ArrowRight => match input.selection() {
+                Some(range) if !shift => input.move_to(range.end, false),
+                _ => {
+                    let position = input.next(ctrl);
+                    input.move_to(position, shift);
+                }
+            },
+            KeyCode::Home => input.move_to(0, shift),
+            KeyCode::End => {
+                let end = input.value.len();
+                input.move_to(end, shift);
+            }
+            KeyCode::Backspace => input.backspace(ctrl),
+            KeyCode::Delete => input.delete(ctrl),
+            KeyCode::KeyA if ctrl => {
+                shortcut = true;
+                input.select_all();
+            }
+            KeyCode::KeyC | KeyCode::KeyX if ctrl => {
+                shortcut = true;
+                let Some(selected) = input.selected_text().map(str::to_owned) else {
+                    continue;
+                };
+                if let Some(clipboard) = clipboard.get() {
+                    if let Err(e) = clipboard.set_text(selected) {
+                        warn!("Could not copy: {e}");
+                    } else if event.key_code == KeyCode::KeyX {
+                        input.delete_selection();
+                    }
+                }
+            }
+            KeyCode::KeyV if ctrl => {
+                shortcut = true;
+                match clipboard.get().map(|c| c.get_text()) {
+                    // only the first line, these are single line inputs
+                    Some(Ok(text)) => input.insert(text.lines().next().unwrap_or_default()),
+                    Some(Err(e)) => warn!("Could not paste: {e}"),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if shortcut {
+        // the letters of the shortcuts
+        char_input_events.clear();
+        return;
+    }
+    for event in char_input_events.read() {
+        input.insert(&event.char);
+    }
+}
+
+fn render_text_inputs(mut inputs: Query<(&TextInput, &mut Text), Changed<TextInput>>) {
+    for (input, mut text) in &mut inputs {
+        let style = |color| TextStyle {
+            color,
+            ..text.sections[0].style.clone()
+        };
+        let cursor = if input.focused { CURSOR } else { "" };
+
+        let sections = if input.value.is_empty() {
+            vec![
+                TextSection::new(cursor, style(TEXT_COLOR)),
+                TextSection::new(input.placeholder.clone(), style(PLACEHOLDER_COLOR)),
+            ]
+        } else {
+            let selection = input.selection().unwrap_or(input.cursor..input.cursor);
+            let parts = [
+                (&input.value[..selection.start], TEXT_COLOR),
+                (&input.value[selection.clone()], SELECTION_COLOR),
+                (&input.value[selection.end..], TEXT_COLOR),
+            ];
+            let mut sections: Vec<_> = parts
+                .into_iter()
+                .map(|(part, color)| TextSection::new(part, style(color)))
+                .collect();
+            // the cursor goes at whichever end of the selection it is
+            let cursor_index = if input.cursor == selection.start {
+                1
+            } else {
+                3
+            };
+            sections.insert(cursor_index, TextSection::new(cursor, style(TEXT_COLOR)));
+            sections
+        };
+        text.sections = sections;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(value: &str) -> TextInput {
+        TextInput::new(100).with_value(value)
+    }
+
+    #[test]
+    fn multibyte_characters_move_as_one() {
+        let mut input = input("žluť");
+        assert_eq!(input.cursor, "žluť".len());
+        input.backspace(false);
+        assert_eq!(input.value(), "žlu");
+        assert_eq!(input.previous(false), "žl".len());
+        input.move_to(0, false);
+        let position = input.next(false);
+        assert_eq!(position, 'ž'.len_utf8());
+        input.move_to(position, false);
+        input.insert("é");
+        assert_eq!(input.value(), "žélu");
+        input.delete(false);
+        assert_eq!(input.value(), "žéu");
+    }
+
+    #[test]
+    fn word_moves_skip_whitespace_and_the_word() {
+        let mut input = input("hello  big world");
+        assert_eq!(input.previous(true), "hello  big ".len());
+        input.move_to("hello  big".len(), false);
+        assert_eq!(input.previous(true), "hello  ".len());
+        input.move_to("hello".len(), false);
+        assert_eq!(input.previous(true), 0);
+        assert_eq!(input.next(true), "hello  big".len());
+        input.move_to("hello  big world".len(), false);
+        assert_eq!(input.next(true), "hello  big world".len());
+        input.move_to(0, false);
+        assert_eq!(input.previous(true), 0);
+    }
+
+    #[test]
+    fn word_backspace_deletes_the_word() {
+        let mut input = input("one two ");
+        input.backspace(true);
+        assert_eq!(input.value(), "one ");
+    }
+
+    #[test]
+    fn insert_replaces_the_selection() {
+        let mut input = input("hello world");
+        input.move_to(0, false);
+        input.move_to("hello".len(), true);
+        assert_eq!(input.selected_text(), Some("hello"));
+        input.insert("bye");
+        assert_eq!(input.value(), "bye world");
+        assert_eq!(input.cursor, "bye".len());
+        assert_eq!(input.selection(), None);
+
+        input.select_all();
+        input.insert("x");
+        assert_eq!(input.value(), "x");
+    }
+
+    #[test]
+    fn max_len_counts_characters() {
+        let mut input = TextInput::new(3).with_value("ěščř");
+        assert_eq!(input.value(), "ěšč");
+        input.insert("a");
+        assert_eq!(input.value(), "ěšč");
+
+        // the selection makes room before the length is checked
+        input.move_to(0, false);
+        input.move_to("ě".len(), true);
+        input.insert("ab");
+        assert_eq!(input.value(), "ašč");
+    }
+
+    #[test]
+    fn disallowed_and_control_characters_are_dropped() {
+        let input = TextInput::new(10)
+            .with_allowed(|c| c.is_ascii_digit())
+            .with_value("1a2\n3");
+        assert_eq!(input.value(), "123");
+    }
+}
@@ -0,0 +1,29 @@
+//! Keeps the list of public servers for the server browser, see [`petri_shared::registry`]
+//!
+//! Listens on localhost unless given another address, e.g. `petri_registry 0.0.0.0:8991`
+
+mod plugin;
+
+use std::time::Duration;
+
+use bevy::{
+    app::{RunMode, ScheduleRunnerPlugin},
+    log::LogPlugin,
+    prelude::*,
+};
+
+use crate::plugin::RegistryPlugin;
+
+fn main() {
+    App::new()
+        .add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin {
+                run_mode: RunMode::Loop {
+                    wait: Some(Duration::from_millis(10)),
+                },
+            }),
+            LogPlugin::default(),
+            RegistryPlugin,
+        ))
+        .run();
+}
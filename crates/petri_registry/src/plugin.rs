@@ -0,0 +1,283 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use bevy::{prelude::*, time::common_conditions::on_timer, utils::HashMap};
+use petri_shared::{
+    discovery::{receive_datagrams, truncate_server_name, ServerInfo},
+    registry::{
+        RegisteredServer, RegistryRequest, RegistryResponse, HEARTBEAT_INTERVAL, MAX_HOST_LEN,
+        REGISTRY_PORT, REGISTRY_REQUEST_LEN, SERVERS_PER_PAGE,
+    },
+};
+
+pub struct RegistryPlugin;
+
+impl Plugin for RegistryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Registry>()
+            .add_systems(Startup, open_registry_socket.map(Result::unwrap))
+            .add_systems(
+                Update,
+                (
+                    handle_requests,
+                    expire_stale_servers.run_if(on_timer(Duration::from_secs(1))),
+                ),
+            );
+    }
+}
+
+/// Servers that missed this many heartbeats are dropped from the list
+const STALE_AFTER: Duration = Duration::from_secs(HEARTBEAT_INTERVAL.as_secs() * 3);
+/// Keeps a single machine from filling the list
+const MAX_SERVERS_PER_IP: usize = 4;
+
+/// Registered servers by the address their heartbeats come from
+#[derive(Resource, Default, Debug)]
+struct Registry(HashMap<SocketAddr, Entry>);
+
+#[derive(Debug)]
+struct Entry {
+    host: String,
+    info: ServerInfo,
+    last_seen: Duration,
+}
+
+#[derive(Resource)]
+struct RegistrySocket(UdpSocket);
+
+fn open_registry_socket(mut commands: Commands) -> anyhow::Result<()> {
+    let address = match std::env::args().nth(1) {
+        Some(address) => address.parse()?,
+        None => SocketAddr::new(Ipv4Addr::LOCALHOST.into(), REGISTRY_PORT),
+    };
+    info!("Listening on {address}");
+    let socket = UdpSocket::bind(address)?;
+    socket.set_nonblocking(true)?;
+    commands.insert_resource(RegistrySocket(socket));
+    Ok(())
+}
+
+fn handle_requests(socket: Res<RegistrySocket>, mut registry: ResMut<Registry>, time: Res<Time>) {
+    let mut buffer = [0; REGISTRY_REQUEST_LEN];
+    let received = receive_datagrams(&socket.0, &mut buffer, |request, from| {
+        let Some(request) = RegistryRequest::from_bytes(request) else {
+            return;
+        };
+
+        match request {
+            RegistryRequest::Heartbeat { info, public_host } => {
+                register(&mut registry, from, info, public_host, time.elapsed());
+            }
+            RegistryRequest::Goodbye => {
+                if let Some(entry) = registry.0.remove(&from) {
+                    info!("{} ({from}) left", entry.info.name);
+                }
+            }
+            RegistryRequest::List { nonce, page } => {
+                let response = list_page(&registry, nonce, page);
+                if let Err(e) = socket.0.send_to(&response.to_bytes(), from) {
+                    debug!("Could not answer list request from {from}: {e}");
+                }
+            }
+        }
+    });
+    if let Err(e) = received {
+        debug!("Registry socket error: {e}");
+    }
+}
+
+fn register(
+    registry: &mut Registry,
+    from: SocketAddr,
+    mut info: ServerInfo,
+    public_host: Option<String>,
+    now: Duration,
+) {
+    if public_host.as_ref().is_some_and(|h| h.len() > MAX_HOST_LEN) {
+        debug!("Ignoring heartbeat from {from} with a too long host");
+        return;
+    }
+    if !registry.0.contains_key(&from) {
+        let from_same_ip = registry.0.keys().filter(|a| a.ip() == from.ip()).count();
+        if from_same_ip >= MAX_SERVERS_PER_IP {
+            debug!("Ignoring heartbeat from {from}, {from_same_ip} servers already use that IP");
+            return;
+        }
+    }
+
+    // keep the list pages within the request size
+    info.name = truncate_server_name(&info.name);
+    info.map = truncate_server_name(&info.map);
+    let host = public_host.unwrap_or_else(|| from.ip().to_string());
+    let entry = Entry {
+        host,
+        info,
+        last_seen: now,
+    };
+    match registry.0.insert(from, entry) {
+        None => {
+            let entry = &registry.0[&from];
+            info!("{} ({from}) joined as {}", entry.info.name, entry.host);
+        }
+        Some(_) => trace!("Heartbeat from {from}"),
+    }
+}
+
+fn list_page(registry: &Registry, nonce: u64, page: u32) -> RegistryResponse {
+    // sorted so that pages stay consistent between requests
+    let mut servers: Vec<_> = registry.0.iter().collect();
+    servers.sort_by_key(|(address, _)| **address);
+
+    let page_count = servers.len().div_ceil(SERVERS_PER_PAGE).max(1) as u32;
+    let servers = servers
+        .into_iter()
+        .skip(page as usize * SERVERS_PER_PAGE)
+        .take(SERVERS_PER_PAGE)
+        .map(|(_, entry)| RegisteredServer {
+            host: entry.host.clone(),
+            info: entry.info.clone(),
+        })
+        .collect();
+    RegistryResponse {
+        nonce,
+        page,
+        page_count,
+        servers,
+    }
+}
+
+fn expire_stale_servers(mut registry: ResMut<Registry>, time: Res<Time>) {
+    drop_stale(&mut registry, time.elapsed());
+}
+
+fn drop_stale(registry: &mut Registry, now: Duration) {
+    registry.0.retain(|address, entry| {
+        let fresh = now - entry.last_seen < STALE_AFTER;
+        if !fresh {
+            info!("{} ({address}) stopped sending heartbeats", entry.info.name);
+        }
+        fresh
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str) -> ServerInfo {
+        ServerInfo {
+            name: name.to_owned(),
+            map: "level".to_owned(),
+            players: 1,
+            max_clients: 10,
+            protocol_version: 1,
+            game_port: 8989,
+        }
+    }
+
+    fn address(ip: [u8; 4], port: u16) -> SocketAddr {
+        SocketAddr::from((ip, port))
+    }
+
+    /// Registers `count` servers from different machines
+    fn registry_with(count: u8) -> Registry {
+        let mut registry = Registry::default();
+        for i in 0..count {
+            let from = address([10, 0, 0, i], 5000);
+            register(
+                &mut registry,
+                from,
+                info(&format!("server {i}")),
+                None,
+                Duration::ZERO,
+            );
+        }
+        registry
+    }
+
+    #[test]
+    fn one_ip_registers_a_few_servers() {
+        let mut registry = Registry::default();
+        for port in 0..6 {
+            let from = address([10, 0, 0, 1], 5000 + port);
+            register(&mut registry, from, info("spam"), None, Duration::ZERO);
+        }
+        assert_eq!(registry.0.len(), MAX_SERVERS_PER_IP);
+
+        // the ones already registered keep their place, other machines still get in
+        let first = address([10, 0, 0, 1], 5000);
+        register(&mut registry, first, info("renamed"), None, Duration::ZERO);
+        assert_eq!(registry.0[&first].info.name, "renamed");
+        register(
+            &mut registry,
+            address([10, 0, 0, 2], 5000),
+            info("other"),
+            None,
+            Duration::ZERO,
+        );
+        assert_eq!(registry.0.len(), MAX_SERVERS_PER_IP + 1);
+    }
+
+    #[test]
+    fn host_defaults_to_the_heartbeat_address() {
+        let mut registry = Registry::default();
+        let from = address([10, 0, 0, 1], 5000);
+        register(&mut registry, from, info("a"), None, Duration::ZERO);
+        assert_eq!(registry.0[&from].host, "10.0.0.1");
+        register(
+            &mut registry,
+            from,
+            info("a"),
+            Some("petri.example".to_owned()),
+            Duration::ZERO,
+        );
+        assert_eq!(registry.0[&from].host, "petri.example");
+    }
+
+    #[test]
+    fn list_is_paged() {
+        let registry = registry_with(12);
+        let pages: Vec<_> = (0..4).map(|page| list_page(&registry, 7, page)).collect();
+        assert!(pages.iter().all(|p| p.page_count == 3 && p.nonce == 7));
+        let sizes: Vec<_> = pages.iter().map(|p| p.servers.len()).collect();
+        assert_eq!(sizes, [SERVERS_PER_PAGE, SERVERS_PER_PAGE, 2, 0]);
+
+        // every server is on exactly one page
+        let mut names: Vec<_> = pages
+            .iter()
+            .flat_map(|p| p.servers.iter().map(|s| s.info.name.clone()))
+            .collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 12);
+    }
+
+    #[test]
+    fn empty_list_has_one_page() {
+        let page = list_page(&Registry::default(), 0, 0);
+        assert_eq!(page.page_count, 1);
+        assert!(page.servers.is_empty());
+    }
+
+    #[test]
+    fn servers_without_heartbeats_are_dropped() {
+        let mut registry = registry_with(2);
+        let refreshed = address([10, 0, 0, 1], 5000);
+        register(
+            &mut registry,
+            refreshed,
+            info("server 1"),
+            None,
+            HEARTBEAT_INTERVAL,
+        );
+
+        drop_stale(&mut registry, STALE_AFTER - Duration::from_millis(1));
+        assert_eq!(registry.0.len(), 2);
+        drop_stale(&mut registry, STALE_AFTER);
+        assert_eq!(registry.0.keys().collect::<Vec<_>>(), [&refreshed]);
+        drop_stale(&mut registry, HEARTBEAT_INTERVAL + STALE_AFTER);
+        assert!(registry.0.is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use petri_shared::{get_player_capsule_size, Appearance, Motion};
+
+/// Picks the models of players and monsters, and tells clients how characters move
+pub struct CharactersPlugin;
+
+impl Plugin for CharactersPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(CharacterModels {
+            player: std::env::var("PETRI_PLAYER_MODEL").ok(),
+            monster: std::env::var("PETRI_MONSTER_MODEL").ok(),
+        })
+        .add_systems(Update, update_motion);
+    }
+}
+
+/// glTF models in the clients' assets, the shapes are used without them.
+/// Set with the `PETRI_PLAYER_MODEL` and `PETRI_MONSTER_MODEL` environment variables.
+#[derive(Resource, Debug)]
+pub(crate) struct CharacterModels {
+    player: Option<String>,
+    monster: Option<String>,
+}
+
+impl CharacterModels {
+    pub(crate) fn player(&self) -> Appearance {
+        self.player
+            .clone()
+            .map_or(Appearance::Capsule, Appearance::Character)
+    }
+
+    pub(crate) fn monster(&self) -> Appearance {
+        self.monster
+            .clone()
+            .map_or(Appearance::Box, Appearance::Character)
+    }
+}
+
+/// How far below the bottom of the capsule still counts as standing on the ground
+const GROUND_MARGIN: f32 = 0.1;
+
+fn update_motion(
+    mut characters: Query<(Entity, &GlobalTransform, &Velocity, &mut Motion)>,
+    rapier: Res<RapierContext>,
+) {
+    let (capsule_diameter, capsule_segment_half_height) = get_player_capsule_size();
+    let half_height = capsule_segment_half_height + capsule_diameter / 2.0;
+    for (entity, transform, velocity, mut motion) in &mut characters {
+        let grounded = rapier
+            .cast_ray(
+                transform.translation(),
+                Vec3::NEG_Y,
+                half_height + GROUND_MARGIN,
+                true,
+                QueryFilter::default().exclude_rigid_body(entity),
+            )
+            .is_some();
+        let new_motion = Motion {
+            velocity: velocity.linvel,
+            grounded,
+        };
+        if !motion.is_close_to(&new_motion) {
+            *motion = new_motion;
+        }
+    }
+}
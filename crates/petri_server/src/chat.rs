@@ -0,0 +1,155 @@
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use petri_shared::{ChatBroadcast, ChatMessage, Tint, MAX_CHAT_MESSAGE_LEN};
+
+use crate::{plugin::PlayerMap, rate_limit::RateLimitedEvents};
+
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, relay_chat);
+    }
+}
+
+/// Words that are replaced with asterisks before relaying
+const BANNED_WORDS: &[&str] = &["fuck", "shit", "cunt", "bitch", "asshole"];
+
+fn relay_chat(
+    mut events: RateLimitedEvents<ChatMessage>,
+    mut broadcast: EventWriter<ToClients<ChatBroadcast>>,
+    player_map: Res<PlayerMap>,
+    players: Query<(Option<&Name>, &Tint)>,
+) {
+    for FromClient { client_id, event } in events.read() {
+        let Some(text) = sanitize(&event.0) else {
+            continue;
+        };
+        let Some(Ok((name, tint))) = player_map.0.get(client_id).map(|e| players.get(*e)) else {
+            error!("Chat message from unknown client {client_id}");
+            continue;
+        };
+        let name = name.map_or_else(|| format!("Player {client_id}"), |n| n.to_string());
+
+        info!("[chat] {name}: {text}");
+        broadcast.send(ToClients {
+            mode: SendMode::Broadcast,
+            event: ChatBroadcast {
+                sender: *client_id,
+                name,
+                tint: tint.0,
+                text,
+            },
+        });
+    }
+}
+
+/// Strips control and format characters, limits the length and censors
+/// banned words. Returns `None` if nothing worth relaying is left.
+fn sanitize(text: &str) -> Option<String> {
+    let printable: String = text
+        .chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .filter(|c| !c.is_control() && !is_format(*c))
+        .take(MAX_CHAT_MESSAGE_LEN)
+        .collect();
+
+    let censored = printable
+        .split(' ')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let bare = word
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase();
+            if BANNED_WORDS.contains(&bare.as_str()) {
+                "*".repeat(word.chars().count())
+            } else {
+                word.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    (!censored.is_empty()).then_some(censored)
+}
+
+/// Whether `c` is an invisible format character (general category `Cf`),
+/// such as a bidi override or a zero-width joiner. Those let a message
+/// reverse or hide parts of the chat, and split banned words.
+fn is_format(c: char) -> bool {
+    matches!(
+        c,
+        '\u{AD}'
+            | '\u{600}'..='\u{605}'
+            | '\u{61C}'
+            | '\u{6DD}'
+            | '\u{70F}'
+            | '\u{890}'..='\u{891}'
+            | '\u{8E2}'
+            | '\u{180E}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{206F}'
+            | '\u{FEFF}'
+            | '\u{FFF9}'..='\u{FFFB}'
+            | '\u{110BD}'
+            | '\u{110CD}'
+            | '\u{13430}'..='\u{1343F}'
+            | '\u{1BCA0}'..='\u{1BCA3}'
+            | '\u{1D173}'..='\u{1D17A}'
+            | '\u{E0001}'
+            | '\u{E0020}'..='\u{E007F}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_strips_control_characters_and_collapses_whitespace() {
+        assert_eq!(
+            sanitize("  hello\n\tthere\u{7}  you ").as_deref(),
+            Some("hello there you")
+        );
+    }
+
+    #[test]
+    fn sanitize_drops_empty_messages() {
+        assert_eq!(sanitize(""), None);
+        assert_eq!(sanitize(" \n\t\u{0}"), None);
+    }
+
+    #[test]
+    fn sanitize_limits_the_length() {
+        let long = "ž".repeat(MAX_CHAT_MESSAGE_LEN + 10);
+        let text = sanitize(&long).unwrap();
+        assert_eq!(text.chars().count(), MAX_CHAT_MESSAGE_LEN);
+    }
+
+    #[test]
+    fn sanitize_censors_banned_words_in_any_case() {
+        assert_eq!(
+            sanitize("oh SHIT, shitake").as_deref(),
+            Some("oh ***** shitake")
+        );
+    }
+
+    #[test]
+    fn sanitize_strips_bidi_overrides_and_isolates() {
+        assert_eq!(
+            sanitize("hi \u{202E}olleh\u{202C} \u{2066}a\u{2069}\u{2067}b\u{2068}").as_deref(),
+            Some("hi olleh ab")
+        );
+        assert_eq!(sanitize("\u{202E}\u{2066}\u{FEFF}"), None);
+    }
+
+    #[test]
+    fn sanitize_censors_banned_words_split_by_zero_width_characters() {
+        assert_eq!(
+            sanitize("oh sh\u{200D}i\u{200B}t").as_deref(),
+            Some("oh ****")
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use std::net::{SocketAddr, UdpSocket};
+
+use bevy::prelude::*;
+use bevy_replicon::prelude::*;
+use petri_shared::{
+    discovery::{
+        receive_datagrams, truncate_server_name, DiscoveryRequest, DiscoveryResponse, ServerInfo,
+        DISCOVERY_PORT, DISCOVERY_REQUEST_LEN,
+    },
+    PROTOCOL_VERSION,
+};
+
+use crate::plugin::{bind_ip, GAME_PORT, MAX_CLIENTS};
+
+/// Answers server browsers looking for servers on the LAN or measuring ping
+pub struct DiscoveryPlugin;
+
+impl Plugin for DiscoveryPlugin {
+    fn build(&self, app: &mut App) {
+        let server_name =
+            std::env::var("PETRI_SERVER_NAME").unwrap_or_else(|_| DEFAULT_SERVER_NAME.into());
+        app.insert_resource(ServerName(truncate_server_name(&server_name)))
+            .add_systems(Startup, open_discovery_socket.map(Result::unwrap))
+            .add_systems(Update, answer_discovery_requests);
+    }
+}
+
+/// Can be changed with the `PETRI_SERVER_NAME` environment variable
+const DEFAULT_SERVER_NAME: &str = "Petrichor";
+const MAP_NAME: &str = "petrichor4-intro";
+
+/// Shown in server browsers
+#[derive(Resource, Debug)]
+pub(crate) struct ServerName(pub String);
+
+#[derive(Resource)]
+struct DiscoverySocket(UdpSocket);
+
+/// What server browsers are told about this server
+pub(crate) fn server_info(name: &ServerName, server: &RenetServer) -> ServerInfo {
+    ServerInfo {
+        name: name.0.clone(),
+        map: MAP_NAME.into(),
+        players: server.clients_id().len() as u32,
+        max_clients: MAX_CLIENTS as u32,
+        protocol_version: PROTOCOL_VERSION,
+        game_port: GAME_PORT,
+    }
+}
+
+fn open_discovery_socket(mut commands: Commands) -> anyhow::Result<()> {
+    let address = SocketAddr::new(bind_ip(), DISCOVERY_PORT);
+    info!("Answering discovery requests on {address}");
+    let socket = UdpSocket::bind(address)?;
+    socket.set_nonblocking(true)?;
+    commands.insert_resource(DiscoverySocket(socket));
+    Ok(())
+}
+
+fn answer_discovery_requests(
+    discovery: Res<DiscoverySocket>,
+    name: Res<ServerName>,
+    server: Res<RenetServer>,
+) {
+    let mut buffer = [0; DISCOVERY_REQUEST_LEN];
+    let received = receive_datagrams(&discovery.0, &mut buffer, |request, from| {
+        let Some(request) = DiscoveryRequest::from_bytes(request) else {
+            return;
+        };
+
+        let response = DiscoveryResponse {
+            nonce: request.nonce,
+            info: server_info(&name, &server),
+        };
+        if let Err(e) = discovery.0.send_to(&response.to_bytes(), from) {
+            debug!("Could not answer discovery request from {from}: {e}");
+        }
+    });
+    if let Err(e) = received {
+        debug!("Discovery socket error: {e}");
+    }
+}
@@ -1,12 +1,15 @@
 use bevy::{
     app::{App, Plugin},
-    prelude::{Color, Commands, Component, Name, Startup, Transform, TransformBundle},
+    prelude::{Color, Commands, Component, Name, Res, Startup, Transform, TransformBundle},
     utils::default,
 };
-use bevy_rapier3d::{dynamics::LockedAxes, prelude::Collider};
-use petri_shared::{get_player_capsule_size, Appearance, ReplicationBundle, Tint};
+use bevy_rapier3d::{
+    dynamics::{LockedAxes, Velocity},
+    prelude::Collider,
+};
+use petri_shared::{get_player_capsule_size, Motion, ReplicationBundle, Tint};
 
-use crate::plugin::PhysicsBundle;
+use crate::{characters::CharacterModels, plugin::PhysicsBundle};
 
 pub struct EnemyPlugin;
 
@@ -19,13 +22,13 @@ impl Plugin for EnemyPlugin {
 #[derive(Component)]
 struct Monster;
 
-fn spawn_monster(mut command: Commands) {
+fn spawn_monster(mut command: Commands, models: Res<CharacterModels>) {
     let (capsule_diameter, capsule_segment_half_height) = get_player_capsule_size();
 
     command.spawn((
         Name::new("Monster"),
         Monster,
-        ReplicationBundle::new(Tint(Color::PINK), Appearance::Box),
+        ReplicationBundle::new(Tint(Color::PINK), models.monster()),
         PhysicsBundle {
             collider: Collider::capsule_y(capsule_segment_half_height, capsule_diameter / 2.0),
             trans: TransformBundle {
@@ -35,5 +38,7 @@ fn spawn_monster(mut command: Commands) {
             ..default()
         },
         LockedAxes::ROTATION_LOCKED,
+        Motion::default(),
+        Velocity::zero(),
     ));
 }
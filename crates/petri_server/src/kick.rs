@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_replicon::{
+    prelude::*,
+    renet::{transport::NetcodeServerTransport, ClientId, ServerEvent},
+};
+use petri_shared::{protocol_version, DisconnectNotice, PROTOCOL_VERSION};
+
+/// Disconnects clients after telling them why
+pub struct KickPlugin;
+
+impl Plugin for KickPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingKicks>()
+            .add_event::<Kick>()
+            .add_systems(
+                Update,
+                (check_client_versions, send_kick_notices, disconnect_kicked).chain(),
+            );
+    }
+}
+
+/// Time for the notice to reach the client before it is disconnected
+const KICK_GRACE: Duration = Duration::from_millis(500);
+
+#[derive(Event, Debug)]
+pub struct Kick {
+    pub client_id: ClientId,
+    pub notice: DisconnectNotice,
+}
+
+/// Clients that have been told why they are kicked, and when to disconnect them
+#[derive(Resource, Default, Debug)]
+struct PendingKicks(Vec<(ClientId, Duration)>);
+
+fn check_client_versions(
+    mut server_events: EventReader<ServerEvent>,
+    transport: Res<NetcodeServerTransport>,
+    mut kicks: EventWriter<Kick>,
+) {
+    for event in server_events.read() {
+        let ServerEvent::ClientConnected { client_id } = event else {
+            continue;
+        };
+        let version = transport
+            .user_data(*client_id)
+            .map_or(0, |user_data| protocol_version(&user_data));
+        if version != PROTOCOL_VERSION {
+            info!("Client {client_id} has protocol version {version}, expected {PROTOCOL_VERSION}");
+            kicks.send(Kick {
+                client_id: *client_id,
+                notice: DisconnectNotice::VersionMismatch {
+                    server_version: PROTOCOL_VERSION,
+                },
+            });
+        }
+    }
+}
+
+fn send_kick_notices(
+    mut kicks: EventReader<Kick>,
+    mut notices: EventWriter<ToClients<DisconnectNotice>>,
+    mut pending: ResMut<PendingKicks>,
+    time: Res<Time>,
+) {
+    for Kick { client_id, notice } in kicks.read() {
+        info!("Kicking client {client_id}: {notice:?}");
+        notices.send(ToClients {
+            mode: SendMode::Direct(*client_id),
+            event: notice.clone(),
+        });
+        pending.0.push((*client_id, time.elapsed() + KICK_GRACE));
+    }
+}
+
+fn disconnect_kicked(
+    mut pending: ResMut<PendingKicks>,
+    mut server: ResMut<RenetServer>,
+    time: Res<Time>,
+) {
+    pending.0.retain(|(client_id, disconnect_at)| {
+        if time.elapsed() < *disconnect_at {
+            return true;
+        }
+        server.disconnect(*client_id);
+        false
+    });
+}
@@ -1,6 +1,20 @@
 mod blob_assets;
+mod characters;
+mod chat;
+mod discovery;
 mod enemy;
+mod kick;
+mod metrics;
+mod names;
 mod plugin;
+mod props;
+mod rate_limit;
+mod registry;
+mod save;
+mod shutdown;
+mod stats;
+mod validation;
+mod visibility;
 
 use std::time::Duration;
 
@@ -30,6 +44,8 @@ fn main() {
                 .disable::<ClientPlugin>()
                 .set(ServerPlugin {
                     tick_policy: TickPolicy::MaxTickRate(60),
+                    // see `visibility::VisibilityPlugin`
+                    visibility_policy: VisibilityPolicy::Whitelist,
                     ..Default::default()
                 }),
             PetriReplicationSetupPlugin,
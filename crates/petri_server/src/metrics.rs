@@ -0,0 +1,259 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bevy::{prelude::*, time::common_conditions::on_timer, utils::get_short_name};
+use bevy_rapier3d::prelude::PhysicsSet;
+use bevy_replicon::prelude::*;
+use petri_shared::{AdminCommand, ChatMessage, InputBatch, SetName};
+
+/// Serves Prometheus-style metrics over HTTP
+pub struct MetricsPlugin;
+
+const METRICS_PORT: u16 = 9091;
+/// How long a scraper gets to send its request and read the response
+const METRICS_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl Plugin for MetricsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerMetrics>()
+            .add_systems(Startup, start_metrics_server.map(Result::unwrap))
+            .add_systems(
+                Update,
+                (
+                    count_client_events::<InputBatch>,
+                    count_client_events::<SetName>,
+                    count_client_events::<ChatMessage>,
+                    count_client_events::<AdminCommand>,
+                    render_metrics.run_if(on_timer(Duration::from_secs(1))),
+                ),
+            )
+            .add_systems(First, start_tick_timer)
+            .add_systems(Last, stop_tick_timer)
+            .add_systems(
+                PostUpdate,
+                (
+                    start_physics_timer.before(PhysicsSet::StepSimulation),
+                    stop_physics_timer.after(PhysicsSet::StepSimulation),
+                ),
+            );
+    }
+}
+
+/// Counters that are updated by the game systems
+#[derive(Resource, Default, Debug)]
+pub(crate) struct ServerMetrics {
+    /// Received client events by event type
+    events: BTreeMap<String, u64>,
+    /// Client events dropped by the rate limiter by event type
+    rate_limited: BTreeMap<String, u64>,
+    /// Entities despawned for falling out of the world
+    pub(crate) kill_y_despawns: u64,
+    /// Props despawned for getting too old
+    pub(crate) prop_decays: u64,
+    tick_started: Option<Instant>,
+    /// Time spent running the schedules, without waiting for the next tick
+    tick: Duration,
+    physics_step_started: Option<Instant>,
+    physics_step: Duration,
+}
+
+/// The latest rendered metrics, shared with the HTTP thread
+#[derive(Resource)]
+struct MetricsPage(Arc<Mutex<String>>);
+
+fn start_metrics_server(mut commands: Commands) -> anyhow::Result<()> {
+    // fly.io scrapes metrics over the private network
+    let hosted_on_fly = std::env::args().any(|a| a == "--flyio");
+    let ip = if hosted_on_fly {
+        Ipv4Addr::UNSPECIFIED
+    } else {
+        Ipv4Addr::LOCALHOST
+    };
+    let address = SocketAddr::new(ip.into(), METRICS_PORT);
+    info!("Serving metrics on http://{address}/metrics");
+    let listener = TcpListener::bind(address)?;
+
+    let page = Arc::new(Mutex::new(String::new()));
+    let served_page = page.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            // a silent or stuck client must not block every scrape after it
+            if let Err(e) = stream
+                .set_read_timeout(Some(METRICS_TIMEOUT))
+                .and_then(|()| stream.set_write_timeout(Some(METRICS_TIMEOUT)))
+            {
+                warn!("Could not set metrics timeouts: {e}");
+                continue;
+            }
+            // every path serves the metrics, so the request itself is not interesting
+            let mut request = [0; 1024];
+            _ = stream.read(&mut request);
+            let body = served_page.lock().unwrap().clone();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\n\
+                Content-Type: text/plain; version=0.0.4\r\n\
+                Content-Length: {}\r\n\
+                Connection: close\r\n\r\n{body}",
+                body.len()
+            );
+            if let Err(e) = stream.write_all(response.as_bytes()) {
+                warn!("Could not send metrics: {e}");
+            }
+        }
+    });
+
+    commands.insert_resource(MetricsPage(page));
+    Ok(())
+}
+
+impl ServerMetrics {
+    pub(crate) fn count_rate_limited(&mut self, event: String) {
+        *self.rate_limited.entry(event).or_default() += 1;
+    }
+}
+
+fn count_client_events<T: Event>(
+    mut events: EventReader<FromClient<T>>,
+    mut metrics: ResMut<ServerMetrics>,
+) {
+    let count = events.read().count() as u64;
+    if count > 0 {
+        *metrics
+            .events
+            .entry(get_short_name(std::any::type_name::<T>()))
+            .or_default() += count;
+    }
+}
+
+fn start_tick_timer(mut metrics: ResMut<ServerMetrics>) {
+    metrics.tick_started = Some(Instant::now());
+}
+
+fn stop_tick_timer(mut metrics: ResMut<ServerMetrics>) {
+    if let Some(started) = metrics.tick_started.take() {
+        metrics.tick = started.elapsed();
+    }
+}
+
+fn start_physics_timer(mut metrics: ResMut<ServerMetrics>) {
+    metrics.physics_step_started = Some(Instant::now());
+}
+
+fn stop_physics_timer(mut metrics: ResMut<ServerMetrics>) {
+    if let Some(started) = metrics.physics_step_started.take() {
+        metrics.physics_step = started.elapsed();
+    }
+}
+
+fn render_metrics(
+    metrics: Res<ServerMetrics>,
+    page: Res<MetricsPage>,
+    server: Res<RenetServer>,
+    entities: Query<()>,
+) {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, values: &[(String, f64)]| {
+        _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+        for (labels, value) in values {
+            _ = writeln!(out, "{name}{labels} {value}");
+        }
+    };
+
+    metric(
+        "petri_tick_duration_seconds",
+        "gauge",
+        "Duration of the last server tick",
+        &[(String::new(), metrics.tick.as_secs_f64())],
+    );
+    metric(
+        "petri_physics_step_seconds",
+        "gauge",
+        "Duration of the last physics step",
+        &[(String::new(), metrics.physics_step.as_secs_f64())],
+    );
+    metric(
+        "petri_entities",
+        "gauge",
+        "Number of entities in the world",
+        &[(String::new(), entities.iter().count() as f64)],
+    );
+
+    let clients = server.clients_id();
+    metric(
+        "petri_connected_clients",
+        "gauge",
+        "Number of connected clients",
+        &[(String::new(), clients.len() as f64)],
+    );
+    let infos: Vec<_> = clients
+        .iter()
+        .filter_map(|id| Some((id, server.network_info(*id).ok()?)))
+        .collect();
+    metric(
+        "petri_client_sent_bytes_per_second",
+        "gauge",
+        "Bandwidth from the server to the client",
+        &infos
+            .iter()
+            .map(|(id, info)| (format!("{{client=\"{id}\"}}"), info.bytes_sent_per_second))
+            .collect::<Vec<_>>(),
+    );
+    metric(
+        "petri_client_received_bytes_per_second",
+        "gauge",
+        "Bandwidth from the client to the server",
+        &infos
+            .iter()
+            .map(|(id, info)| {
+                (
+                    format!("{{client=\"{id}\"}}"),
+                    info.bytes_received_per_second,
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    metric(
+        "petri_client_events_total",
+        "counter",
+        "Client events received by the server",
+        &metrics
+            .events
+            .iter()
+            .map(|(event, count)| (format!("{{event=\"{event}\"}}"), *count as f64))
+            .collect::<Vec<_>>(),
+    );
+    metric(
+        "petri_rate_limited_events_total",
+        "counter",
+        "Client events dropped for exceeding the rate limit",
+        &metrics
+            .rate_limited
+            .iter()
+            .map(|(event, count)| (format!("{{event=\"{event}\"}}"), *count as f64))
+            .collect::<Vec<_>>(),
+    );
+    metric(
+        "petri_kill_y_despawns_total",
+        "counter",
+        "Entities despawned for falling out of the world",
+        &[(String::new(), metrics.kill_y_despawns as f64)],
+    );
+    metric(
+        "petri_prop_decays_total",
+        "counter",
+        "Props despawned for getting too old",
+        &[(String::new(), metrics.prop_decays as f64)],
+    );
+
+    *page.0.lock().unwrap() = out;
+}
@@ -0,0 +1,153 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_replicon::prelude::*;
+use petri_shared::{NameAccepted, Player, SetName, MAX_NAME_LEN};
+
+use crate::{plugin::PlayerMap, rate_limit::RateLimitedEvents};
+
+pub struct NamesPlugin;
+
+impl Plugin for NamesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, receive_names);
+    }
+}
+
+/// Used when nothing is left of the requested name after validation
+const DEFAULT_NAME: &str = "Player";
+
+fn receive_names(
+    mut events: RateLimitedEvents<SetName>,
+    mut accepted: EventWriter<ToClients<NameAccepted>>,
+    player_map: Res<PlayerMap>,
+    named_players: Query<(Entity, &Name), With<Player>>,
+    mut commands: Commands,
+) {
+    // names are only inserted when commands are applied,
+    // so track the ones given out during this run as well
+    let mut taken: HashMap<Entity, String> = named_players
+        .iter()
+        .map(|(entity, name)| (entity, name.to_lowercase()))
+        .collect();
+
+    for FromClient { client_id, event } in events.read() {
+        let Some(&entity) = player_map.0.get(client_id) else {
+            error!("Name {:?} from unknown client {client_id}", event.0);
+            continue;
+        };
+        let requested = sanitize_name(&event.0);
+        let name = make_unique(&requested, |candidate| {
+            let candidate = candidate.to_lowercase();
+            taken
+                .iter()
+                .any(|(other, name)| *other != entity && *name == candidate)
+        });
+        info!(
+            "Client {client_id} asked for name {:?}, got {name:?}",
+            event.0
+        );
+
+        taken.insert(entity, name.to_lowercase());
+        commands.entity(entity).insert(Name::new(name.clone()));
+        accepted.send(ToClients {
+            mode: SendMode::Direct(*client_id),
+            event: NameAccepted(name),
+        });
+    }
+}
+
+/// Keeps only letters, digits, spaces, `_` and `-`, collapses whitespace
+/// and limits the length to [`MAX_NAME_LEN`].
+fn sanitize_name(requested: &str) -> String {
+    let allowed: String = requested
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '_' | '-'))
+        .collect();
+    let name = allowed
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_NAME_LEN)
+        .collect::<String>();
+    let name = name.trim_end().to_owned();
+
+    if name.is_empty() {
+        DEFAULT_NAME.to_owned()
+    } else {
+        name
+    }
+}
+
+/// Appends a number to the name until `is_taken` is happy with it
+fn make_unique(name: &str, is_taken: impl Fn(&str) -> bool) -> String {
+    let mut candidate = name.to_owned();
+    let mut n = 1;
+    while is_taken(&candidate) {
+        n += 1;
+        let suffix = n.to_string();
+        let base: String = name
+            .chars()
+            .take(MAX_NAME_LEN.saturating_sub(suffix.len()))
+            .collect();
+        candidate = format!("{}{suffix}", base.trim_end());
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_collapses_whitespace() {
+        assert_eq!(sanitize_name("  big \t\n  bob "), "big bob");
+    }
+
+    #[test]
+    fn sanitize_drops_other_characters() {
+        assert_eq!(sanitize_name("<b>Žofie</b>_1-2!"), "bŽofieb_1-2");
+    }
+
+    #[test]
+    fn sanitize_truncates_without_trailing_space() {
+        let name = sanitize_name(&format!("{} tail", "a".repeat(MAX_NAME_LEN - 1)));
+        assert_eq!(name, "a".repeat(MAX_NAME_LEN - 1));
+    }
+
+    #[test]
+    fn sanitize_falls_back_to_the_default() {
+        assert_eq!(sanitize_name(""), DEFAULT_NAME);
+        assert_eq!(sanitize_name(" !?\u{200b} "), DEFAULT_NAME);
+    }
+
+    #[test]
+    fn unique_names_get_numbers() {
+        assert_eq!(make_unique("bob", |_| false), "bob");
+        let taken = ["bob", "bob2", "bob3"];
+        assert_eq!(make_unique("bob", |n| taken.contains(&n)), "bob4");
+    }
+
+    #[test]
+    fn suffix_fits_in_a_long_name() {
+        let long = "a".repeat(MAX_NAME_LEN);
+        let name = make_unique(&long, |n| n == long);
+        assert_eq!(name.chars().count(), MAX_NAME_LEN);
+        assert!(name.ends_with("a2"));
+
+        let taken: Vec<String> = (2..10)
+            .map(|n| format!("{}{n}", &long[1..]))
+            .chain([long.clone()])
+            .collect();
+        let name = make_unique(&long, |n| taken.iter().any(|t| t == n));
+        assert_eq!(name.chars().count(), MAX_NAME_LEN);
+        assert!(name.ends_with("a10"));
+    }
+
+    #[test]
+    fn suffix_does_not_follow_a_space() {
+        // the suffix cuts the name off right after the space
+        let name = format!("{} b", "a".repeat(MAX_NAME_LEN - 2));
+        let unique = make_unique(&name, |n| n == name);
+        assert_eq!(unique, format!("{}2", "a".repeat(MAX_NAME_LEN - 2)));
+    }
+}
@@ -15,14 +15,28 @@ use bevy_replicon::{
 };
 use obj::{load_obj, Obj, Position};
 use petri_shared::{
-    get_player_capsule_size, AdminCommand, Aim, Appearance, MoveDirection, Player, ReplicatedAim,
-    ReplicatedPos, ReplicationBundle, SetName, Tint,
+    get_player_capsule_size, Admin, AdminCommand, Appearance, InputBatch, InputFrame, Motion,
+    Player, PlayerStats, ReplicatedAim, ReplicatedPos, ReplicationBundle, Tint, INPUT_REDUNDANCY,
 };
 use rand::random;
 
 use crate::{
     blob_assets::{Blob, BlobLoaderPlugin},
+    characters::{CharacterModels, CharactersPlugin},
+    chat::ChatPlugin,
+    discovery::DiscoveryPlugin,
     enemy::EnemyPlugin,
+    kick::KickPlugin,
+    metrics::{MetricsPlugin, ServerMetrics},
+    names::NamesPlugin,
+    props::{check_budget, last_spawn, Prop, PropSettings, PropsPlugin, SpawnCounter},
+    rate_limit::{RateLimitPlugin, RateLimitedEvents},
+    registry::RegistryPlugin,
+    save::{Persistent, SavePlugin},
+    shutdown::ShutdownPlugin,
+    stats::StatsPlugin,
+    validation::{validate_frame, InputViolation, ValidationPlugin, ValidationSettings, Violation},
+    visibility::{AlwaysRelevant, VisibilityPlugin},
 };
 
 pub struct PetriServerPlugin;
@@ -30,7 +44,21 @@ pub struct PetriServerPlugin;
 impl Plugin for PetriServerPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(BlobLoaderPlugin)
+            .add_plugins(CharactersPlugin)
             .add_plugins(EnemyPlugin)
+            .add_plugins(ChatPlugin)
+            .add_plugins(NamesPlugin)
+            .add_plugins(StatsPlugin)
+            .add_plugins(MetricsPlugin)
+            .add_plugins(VisibilityPlugin)
+            .add_plugins(ValidationPlugin)
+            .add_plugins(RateLimitPlugin)
+            .add_plugins(PropsPlugin)
+            .add_plugins(SavePlugin)
+            .add_plugins(ShutdownPlugin)
+            .add_plugins(KickPlugin)
+            .add_plugins(DiscoveryPlugin)
+            .add_plugins(RegistryPlugin)
             .init_resource::<ObjFileWithColliderHandle>()
             .init_resource::<PlayerMap>()
             .add_systems(
@@ -41,10 +69,8 @@ impl Plugin for PetriServerPlugin {
                 Update,
                 (
                     server_event_system,
-                    receive_names,
                     load_collider_from_mesh,
-                    move_clients,
-                    apply_aim,
+                    apply_inputs,
                     update_player_pos,
                     handle_admin_commands,
                     kill_y,
@@ -52,29 +78,12 @@ impl Plugin for PetriServerPlugin {
             )
             .add_plugins(RapierPhysicsPlugin::<NoUserData>::default());
 
-        fn receive_names(
-            mut events: EventReader<FromClient<SetName>>,
-            mut clients: Query<(Entity, &Player), Without<Name>>,
-            mut commands: Commands,
-        ) {
-            // FIXME: get entity by client id
-            for event in events.read() {
-                info!("Received name from {:?} {:?}", event.client_id, event.event);
-                for (entity, Player(client_id)) in clients.iter_mut() {
-                    if client_id == &event.client_id {
-                        commands
-                            .entity(entity)
-                            .insert(Name::new(event.event.0.clone()));
-                    }
-                }
-            }
-        }
-
         /// Logs server events and spawns a new player whenever a client connects.
         fn server_event_system(
             mut commands: Commands,
             mut server_event: EventReader<ServerEvent>,
             mut player_map: ResMut<PlayerMap>,
+            models: Res<CharacterModels>,
         ) {
             for event in server_event.read() {
                 match event {
@@ -93,20 +102,19 @@ impl Plugin for PetriServerPlugin {
                                 Player(*client_id),
                                 // FIXME: Players are Admins by default
                                 Admin,
-                                ReplicationBundle::new(
-                                    Tint(Color::rgb(r, g, b)),
-                                    Appearance::Capsule,
-                                ),
+                                // everyone is on the scoreboard
+                                AlwaysRelevant,
+                                ReplicationBundle::new(Tint(Color::rgb(r, g, b)), models.player()),
+                                ReplicatedAim::default(),
+                                Motion::default(),
+                                Velocity::zero(),
+                                LastInputSequence::default(),
                                 PhysicsBundle {
                                     collider: Collider::capsule_y(
                                         capsule_segment_half_height,
                                         capsule_diameter / 2.0,
                                     ),
-                                    trans: TransformBundle::from_transform(Transform::from_xyz(
-                                        random::<f32>() * 3.0 + 1.5,
-                                        2.5,
-                                        random::<f32>() * 3.0 + 1.5,
-                                    )),
+                                    trans: TransformBundle::from_transform(player_spawn_point()),
                                     ..default()
                                 },
                                 LockedAxes::ROTATION_LOCKED,
@@ -125,10 +133,7 @@ impl Plugin for PetriServerPlugin {
                             if let Some(e) = commands.get_entity(e) {
                                 e.despawn_recursive();
                             } else {
-                                info!(
-                                    "Entity for client {client_id} does not exist. \
-                                    Possibly the player has fallen through the floor"
-                                )
+                                warn!("Entity for client {client_id} was already despawned")
                             }
                         } else {
                             info!("Unknown client {client_id} disconnected ")
@@ -138,12 +143,21 @@ impl Plugin for PetriServerPlugin {
             }
         }
 
+        /// Sleeping bodies and changes too small to notice are not replicated
         fn update_player_pos(
-            mut players: Query<(&GlobalTransform, &mut ReplicatedPos), Changed<GlobalTransform>>,
+            mut players: Query<
+                (&GlobalTransform, &mut ReplicatedPos, &Sleeping),
+                Changed<GlobalTransform>,
+            >,
         ) {
-            players.iter_mut().for_each(|(local_pos, mut shared_pos)| {
-                shared_pos.0 = *local_pos;
-            })
+            players
+                .iter_mut()
+                .filter(|(local_pos, shared_pos, sleeping)| {
+                    !sleeping.sleeping && !shared_pos.is_close_to(local_pos)
+                })
+                .for_each(|(local_pos, mut shared_pos, _)| {
+                    shared_pos.0 = *local_pos;
+                })
         }
 
         fn setup_server_networking(
@@ -159,30 +173,26 @@ impl Plugin for PetriServerPlugin {
                 ..Default::default()
             });
 
-            let port = 8989;
-            let hosted_on_fly = std::env::args().any(|a| a == "--flyio");
-
             let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
-            // fly.io requires UDP apps to bind to a specific address
-            // https://fly.io/docs/networking/udp-and-tcp/
-            let ip = if hosted_on_fly {
-                dns_lookup::lookup_host("fly-global-services")
-                    .unwrap()
+            let socket_address = SocketAddr::new(bind_ip(), GAME_PORT);
+            let public_addresses = if socket_address.ip().is_unspecified() {
+                // clients on the LAN and on this machine see different addresses
+                lan_ip()
                     .into_iter()
-                    .find(IpAddr::is_ipv4)
-                    .unwrap()
+                    .chain([Ipv4Addr::LOCALHOST.into()])
+                    .map(|ip| SocketAddr::new(ip, GAME_PORT))
+                    .collect()
             } else {
-                Ipv4Addr::LOCALHOST.into()
+                vec![socket_address]
             };
-            let socket_address = SocketAddr::new(ip, port);
-            info!("Starting server on {socket_address:?}");
+            info!("Starting server on {socket_address:?}, reachable at {public_addresses:?}");
             let socket = UdpSocket::bind(socket_address)?;
             let server_config = ServerConfig {
                 current_time,
-                max_clients: 10,
+                max_clients: MAX_CLIENTS,
                 protocol_id: 0,
                 authentication: ServerAuthentication::Unsecure,
-                public_addresses: vec![socket_address],
+                public_addresses,
             };
             let transport = NetcodeServerTransport::new(server_config, socket)?;
 
@@ -193,8 +203,53 @@ impl Plugin for PetriServerPlugin {
     }
 }
 
+pub(crate) const GAME_PORT: u16 = 8989;
+pub(crate) const MAX_CLIENTS: usize = 10;
+
+/// Where the server sockets are bound: only this machine by default,
+/// every interface with `--lan` and the fly.io UDP address with `--flyio`
+pub(crate) fn bind_ip() -> IpAddr {
+    let has_flag = |flag: &str| std::env::args().any(|a| a == flag);
+    if has_flag("--flyio") {
+        // fly.io requires UDP apps to bind to a specific address
+        // https://fly.io/docs/networking/udp-and-tcp/
+        dns_lookup::lookup_host("fly-global-services")
+            .unwrap()
+            .into_iter()
+            .find(IpAddr::is_ipv4)
+            .unwrap()
+    } else if has_flag("--lan") {
+        Ipv4Addr::UNSPECIFIED.into()
+    } else {
+        Ipv4Addr::LOCALHOST.into()
+    }
+}
+
+/// The address other machines on the LAN can reach this one at,
+/// if there is a network to find it on
+fn lan_ip() -> Option<IpAddr> {
+    // connecting a UDP socket sends nothing, it only picks the outgoing interface
+    let ip = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket.connect((Ipv4Addr::new(8, 8, 8, 8), 53))?;
+            socket.local_addr()
+        })
+        .map(|address| address.ip());
+    match ip {
+        Ok(ip) if !ip.is_unspecified() => Some(ip),
+        Ok(_) => {
+            warn!("No LAN address found, only clients on this machine can join");
+            None
+        }
+        Err(e) => {
+            warn!("No LAN address found, only clients on this machine can join: {e}");
+            None
+        }
+    }
+}
+
 #[derive(Resource, Default, Debug)]
-struct PlayerMap(HashMap<ClientId, Entity>);
+pub(crate) struct PlayerMap(pub(crate) HashMap<ClientId, Entity>);
 
 // TODO: is it ok to create default handle?
 #[derive(Resource, Default)]
@@ -238,73 +293,176 @@ fn load_collider(asset_server: Res<AssetServer>, mut blob: ResMut<ObjFileWithCol
     blob.0 = asset_server.load("level_collider.obj");
 }
 
-fn move_clients(
-    mut events: EventReader<FromClient<MoveDirection>>,
-    mut player: Query<(&Player, &mut ExternalImpulse, &ReadMassProperties)>,
-) {
-    // FIXME: make this a map of client_id to entity and update it once per [Update]
-    let mut player_to_ext_force: HashMap<_, _> = player
-        .iter_mut()
-        .map(|(Player(client_id), force, props)| (client_id, (force, props)))
-        .collect();
-
-    // FIXME: If network loses packets, the player runs slower
-    // TODO: remember the intent for at least half a second?
-    for event in events.read() {
-        const KONSTANTA: f32 = 0.1;
-
-        if let Some((force, props)) = player_to_ext_force.get_mut(&event.client_id) {
-            let normalized = event.event.0.normalize_or_zero();
-            force.impulse = Vec3 {
-                x: normalized.x,
-                y: 0.0,
-                // N.B.
-                z: normalized.y,
-            } * KONSTANTA
-                * props.mass;
-        } else {
-            error!("POLTERGEIST IS MOVING");
-        }
-    }
-}
+/// The sequence number of the latest input frame applied to a player
+#[derive(Component, Default)]
+struct LastInputSequence(u32);
+
+/// How hard one input frame pushes a player, times their mass
+const MOVE_IMPULSE: f32 = 0.1;
 
-fn apply_aim(
-    mut events: EventReader<FromClient<Aim>>,
-    mut player: Query<&mut ReplicatedAim>,
+fn apply_inputs(
+    mut events: RateLimitedEvents<InputBatch>,
+    mut players: Query<(
+        &mut LastInputSequence,
+        &mut ExternalImpulse,
+        &ReadMassProperties,
+        &mut ReplicatedAim,
+    )>,
     map: Res<PlayerMap>,
+    mut violations: EventWriter<InputViolation>,
+    settings: Res<ValidationSettings>,
 ) {
-    for e in events.read() {
-        let Some(entity) = map.0.get(&e.client_id) else {
-            error!("Unknown client id {}", e.client_id);
+    for FromClient { client_id, event } in events.read() {
+        let Some(entity) = map.0.get(client_id) else {
+            error!("POLTERGEIST IS MOVING {client_id}");
+            continue;
+        };
+        let Ok((mut last_sequence, mut force, props, mut aim)) = players.get_mut(*entity) else {
+            error!("Player {client_id} can't be moved");
             continue;
         };
-        let Ok(mut aim) = player.get_mut(*entity) else {
-            error!("Player does not have aim component {}", e.client_id);
+
+        let movement = apply_batch(
+            &event.0,
+            &mut last_sequence.0,
+            &mut aim.0,
+            &settings,
+            |violation| {
+                violations.send(InputViolation {
+                    client_id: *client_id,
+                    violation,
+                });
+            },
+        );
+        // TODO: remember the intent for at least half a second?
+        push(&mut force.impulse, movement, props.mass);
+    }
+}
+
+/// Applies the aim of the frames in `batch` that are newer than `last_sequence`
+/// and returns the sum of their movement
+fn apply_batch(
+    batch: &[InputFrame],
+    last_sequence: &mut u32,
+    aim: &mut Direction3d,
+    settings: &ValidationSettings,
+    mut report: impl FnMut(Violation),
+) -> Vec2 {
+    // honest clients only repeat their latest frames
+    if batch.len() > INPUT_REDUNDANCY {
+        report(Violation::OversizedBatch(batch.len()));
+        return Vec2::ZERO;
+    }
+    // batches overlap, so most of the frames have already been applied
+    let already_applied = *last_sequence;
+    if let Some(newest) = batch.iter().map(|f| f.sequence).max() {
+        if newest > already_applied.saturating_add(INPUT_REDUNDANCY as u32) {
+            report(Violation::SequenceJump {
+                from: already_applied,
+                to: newest,
+            });
+        }
+    }
+    // the first aim only says where the client looks, it turns from our default
+    let mut has_aimed = already_applied > 0;
+    let mut movement = Vec2::ZERO;
+    for frame in batch {
+        if frame.sequence <= *last_sequence {
             continue;
+        }
+        *last_sequence = frame.sequence;
+        let frame = match validate_frame(frame) {
+            Ok(frame) => frame,
+            Err(violation) => {
+                report(violation);
+                continue;
+            }
         };
-        aim.0 = e.event.0;
+
+        let turn = aim.angle_between(*frame.aim);
+        if has_aimed && turn > settings.max_aim_turn_per_frame {
+            report(Violation::AimSnap(turn));
+        }
+        *aim = frame.aim;
+        has_aimed = true;
+
+        if let Some(frame_movement) = frame.movement {
+            movement += frame_movement.normalize_or_zero();
+        }
     }
+    movement
+}
+
+/// Pushes a player along `movement`, by at most one input frame's worth per physics step
+/// however many frames arrive, rapier resets the impulse after each step
+fn push(impulse: &mut Vec3, movement: Vec2, mass: f32) {
+    let max = MOVE_IMPULSE * mass;
+    *impulse += Vec3 {
+        x: movement.x,
+        y: 0.0,
+        // N.B.
+        z: movement.y,
+    } * max;
+    *impulse = impulse.clamp_length_max(max);
 }
 
 #[derive(Bundle, Default)]
 pub struct PhysicsBundle {
     pub impulse: ExternalImpulse,
+    pub sleeping: Sleeping,
     pub collider: Collider,
     pub mass_props: ReadMassProperties,
     pub rigid_body: RigidBody,
     pub trans: TransformBundle,
 }
 
-#[derive(Component)]
-pub struct Admin;
-
+#[allow(clippy::too_many_arguments)]
 fn handle_admin_commands(
     mut commands: Commands,
-    mut admin_commands: EventReader<FromClient<AdminCommand>>,
+    mut admin_commands: RateLimitedEvents<AdminCommand>,
+    admins: Query<&GlobalTransform, With<Admin>>,
+    props: Query<(Entity, &Prop)>,
+    map: Res<PlayerMap>,
+    mut violations: EventWriter<InputViolation>,
+    settings: Res<ValidationSettings>,
+    prop_settings: Res<PropSettings>,
+    mut spawn_counter: ResMut<SpawnCounter>,
+    time: Res<Time>,
 ) {
-    for command in admin_commands.read() {
-        match command.event {
+    // spawned props only show up in queries after the commands are applied
+    let mut pending = HashMap::new();
+
+    for FromClient { client_id, event } in admin_commands.read() {
+        let Some(admin) = map.0.get(client_id).and_then(|e| admins.get(*e).ok()) else {
+            warn!("Client {client_id} is not an admin, ignoring {event:?}");
+            continue;
+        };
+        match *event {
             AdminCommand::SpawnBoxWall { side_size, at } => {
+                if !at.is_finite() || admin.translation().distance(at) > settings.max_build_distance
+                {
+                    violations.send(InputViolation {
+                        client_id: *client_id,
+                        violation: Violation::BuildTooFar(at),
+                    });
+                    continue;
+                }
+                if side_size > settings.max_wall_side {
+                    violations.send(InputViolation {
+                        client_id: *client_id,
+                        violation: Violation::WallTooBig(side_size),
+                    });
+                }
+                let side_size = side_size.min(settings.max_wall_side);
+
+                let count = side_size as usize * side_size as usize;
+                if let Err(e) = check_budget(&props, &pending, *client_id, count, &prop_settings) {
+                    warn!("Client {client_id} can't spawn {count} boxes: {e}");
+                    continue;
+                }
+                *pending.entry(*client_id).or_default() += count;
+
+                let spawn = spawn_counter.next();
                 for xi in 0..side_size {
                     for yi in 0..side_size {
                         commands.spawn((
@@ -318,20 +476,140 @@ fn handle_admin_commands(
                                 // FIXME: boxes probably do not need mass props
                                 ..default()
                             },
-                            // FIXME: boxes don't have aim
                             ReplicationBundle::new(Tint(Color::GREEN), Appearance::Box),
+                            Persistent,
+                            Prop {
+                                owner: Some(*client_id),
+                                spawn,
+                                expires_at: time.elapsed() + prop_settings.lifetime,
+                            },
                         ));
                     }
                 }
             }
+            AdminCommand::UndoLastSpawn => {
+                let last = last_spawn(&props, *client_id);
+                if last.is_empty() {
+                    info!("Client {client_id} has nothing to undo");
+                    continue;
+                }
+                for entity in last {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
         }
     }
 }
 
-fn kill_y(mut commands: Commands, query: Query<(Entity, &GlobalTransform)>) {
-    for (e, t) in query.iter() {
+/// Where players appear when they join or fall out of the world
+fn player_spawn_point() -> Transform {
+    Transform::from_xyz(
+        random::<f32>() * 3.0 + 1.5,
+        2.5,
+        random::<f32>() * 3.0 + 1.5,
+    )
+}
+
+fn kill_y(
+    mut commands: Commands,
+    mut query: Query<(Entity, &GlobalTransform, Option<&mut PlayerStats>)>,
+    mut metrics: ResMut<ServerMetrics>,
+) {
+    for (e, t, stats) in query.iter_mut() {
         if t.translation().y < -1000.0 {
-            commands.entity(e).despawn_recursive();
+            if let Some(mut stats) = stats {
+                // players are put back into the level instead of being despawned
+                stats.deaths += 1;
+                commands
+                    .entity(e)
+                    .insert((player_spawn_point(), Velocity::zero()));
+            } else {
+                commands.entity(e).despawn_recursive();
+                metrics.kill_y_despawns += 1;
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(sequences: impl Iterator<Item = u32>) -> Vec<InputFrame> {
+        sequences
+            .map(|sequence| InputFrame {
+                sequence,
+                movement: Some(Vec2::Y),
+                aim: Direction3d::NEG_Z,
+            })
+            .collect()
+    }
+
+    /// Applies `batch` to a player, returns their impulse and the violations
+    fn apply(batch: &[InputFrame], last_sequence: &mut u32) -> (Vec3, Vec<Violation>) {
+        let mut violations = vec![];
+        let mut impulse = Vec3::ZERO;
+        let mut aim = Direction3d::NEG_Z;
+        let movement = apply_batch(
+            batch,
+            last_sequence,
+            &mut aim,
+            &ValidationSettings::default(),
+            |v| violations.push(v),
+        );
+        push(&mut impulse, movement, 1.0);
+        (impulse, violations)
+    }
+
+    #[test]
+    fn long_batches_dont_push_harder() {
+        let (normal, violations) = apply(&frames(1..=INPUT_REDUNDANCY as u32), &mut 0);
+        assert!(violations.is_empty());
+        assert!(normal.length() > 0.0);
+
+        let (flood, violations) = apply(&frames(1..=50), &mut 0);
+        assert!(flood.length() <= normal.length());
+        assert!(matches!(violations[..], [Violation::OversizedBatch(50)]));
+    }
+
+    #[test]
+    fn push_is_capped_per_step() {
+        let mut impulse = Vec3::ZERO;
+        for _ in 0..10 {
+            push(&mut impulse, Vec2::new(1.0, 1.0), 2.0);
+        }
+        assert!(impulse.length() <= MOVE_IMPULSE * 2.0 + f32::EPSILON);
+    }
+
+    #[test]
+    fn only_new_frames_are_applied() {
+        let mut last_sequence = 0;
+        apply(&frames(1..=4), &mut last_sequence);
+        assert_eq!(last_sequence, 4);
+        // repeated frames are skipped
+        let mut aim = Direction3d::NEG_Z;
+        let movement = apply_batch(
+            &frames(3..=6),
+            &mut last_sequence,
+            &mut aim,
+            &ValidationSettings::default(),
+            |_| panic!("no violations expected"),
+        );
+        assert_eq!(movement, Vec2::Y * 2.0);
+        assert_eq!(last_sequence, 6);
+    }
+
+    #[test]
+    fn sequence_jumps_are_reported() {
+        let mut last_sequence = 10;
+        let (_, violations) = apply(&frames(11..=14), &mut last_sequence);
+        assert!(violations.is_empty());
+
+        let (_, violations) = apply(&frames(100..=103), &mut last_sequence);
+        assert!(matches!(
+            violations[..],
+            [Violation::SequenceJump { from: 14, to: 103 }]
+        ));
+        assert_eq!(last_sequence, 103);
+    }
+}
@@ -0,0 +1,258 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, time::common_conditions::on_timer, utils::HashMap};
+use bevy_replicon::renet::ClientId;
+use thiserror::Error;
+
+use crate::metrics::ServerMetrics;
+
+/// Keeps track of who spawned what and cleans up old props
+pub struct PropsPlugin;
+
+impl Plugin for PropsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PropSettings>()
+            .init_resource::<SpawnCounter>()
+            .add_systems(Update, decay_props.run_if(on_timer(Duration::from_secs(1))));
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct PropSettings {
+    /// Props in the whole world, every one of them is a physics body
+    pub max_props: usize,
+    pub max_props_per_player: usize,
+    /// Props are despawned when they get this old
+    pub lifetime: Duration,
+}
+
+impl Default for PropSettings {
+    fn default() -> Self {
+        Self {
+            max_props: 2000,
+            max_props_per_player: 300,
+            lifetime: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// Something spawned by a player
+#[derive(Component, Debug)]
+pub struct Prop {
+    /// `None` for props loaded from a save until their owner joins, see [`crate::save`]
+    pub owner: Option<ClientId>,
+    /// Props spawned by the same command share this number
+    pub spawn: u32,
+    /// Server time when the prop decays
+    pub expires_at: Duration,
+}
+
+/// Gives out [`Prop::spawn`] numbers
+#[derive(Resource, Default, Debug)]
+pub(crate) struct SpawnCounter(u32);
+
+impl SpawnCounter {
+    pub(crate) fn next(&mut self) -> u32 {
+        self.0 += 1;
+        self.0
+    }
+
+    /// Numbers up to `spawn` are taken, like by props loaded from a save
+    pub(crate) fn skip_past(&mut self, spawn: u32) {
+        self.0 = self.0.max(spawn);
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BudgetExceeded {
+    #[error("the world already has {0} props")]
+    World(usize),
+    #[error("the player already owns {0} props")]
+    Player(usize),
+}
+
+/// Checks that `owner` can spawn `count` more props,
+/// given the existing `props` and the `pending` ones that are not spawned yet
+pub(crate) fn check_budget(
+    props: &Query<(Entity, &Prop)>,
+    pending: &HashMap<ClientId, usize>,
+    owner: ClientId,
+    count: usize,
+    settings: &PropSettings,
+) -> Result<(), BudgetExceeded> {
+    let total = props.iter().len() + pending.values().sum::<usize>();
+    if total + count > settings.max_props {
+        return Err(BudgetExceeded::World(total));
+    }
+    let owned = props.iter().filter(|(_, p)| p.owner == Some(owner)).count()
+        + pending.get(&owner).copied().unwrap_or_default();
+    if owned + count > settings.max_props_per_player {
+        return Err(BudgetExceeded::Player(owned));
+    }
+    Ok(())
+}
+
+/// Props of the latest spawn of `owner` that are still around, an undo removes these
+pub(crate) fn last_spawn(props: &Query<(Entity, &Prop)>, owner: ClientId) -> Vec<Entity> {
+    let owned = || props.iter().filter(|(_, p)| p.owner == Some(owner));
+    let Some(last) = owned().map(|(_, p)| p.spawn).max() else {
+        return vec![];
+    };
+    owned()
+        .filter(|(_, p)| p.spawn == last)
+        .map(|(entity, _)| entity)
+        .collect()
+}
+
+fn decay_props(
+    mut commands: Commands,
+    props: Query<(Entity, &Prop)>,
+    mut metrics: ResMut<ServerMetrics>,
+    time: Res<Time>,
+) {
+    for (entity, prop) in &props {
+        if time.elapsed() > prop.expires_at {
+            commands.entity(entity).despawn_recursive();
+            metrics.prop_decays += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn prop(owner: u64, spawn: u32) -> Prop {
+        Prop {
+            owner: Some(ClientId::from_raw(owner)),
+            spawn,
+            expires_at: Duration::from_secs(60),
+        }
+    }
+
+    fn settings() -> PropSettings {
+        PropSettings {
+            max_props: 10,
+            max_props_per_player: 4,
+            lifetime: Duration::from_secs(60),
+        }
+    }
+
+    fn check(
+        world: &mut World,
+        pending: HashMap<ClientId, usize>,
+        owner: u64,
+        count: usize,
+    ) -> Result<(), BudgetExceeded> {
+        world.run_system_once(move |props: Query<(Entity, &Prop)>| {
+            check_budget(
+                &props,
+                &pending,
+                ClientId::from_raw(owner),
+                count,
+                &settings(),
+            )
+        })
+    }
+
+    #[test]
+    fn spawns_within_budget_are_allowed() {
+        let mut world = World::new();
+        world.spawn(prop(1, 1));
+        world.spawn(prop(2, 2));
+        assert!(check(&mut world, default(), 1, 3).is_ok());
+    }
+
+    #[test]
+    fn player_over_budget_is_refused() {
+        let mut world = World::new();
+        for _ in 0..3 {
+            world.spawn(prop(1, 1));
+        }
+        assert!(matches!(
+            check(&mut world, default(), 1, 2),
+            Err(BudgetExceeded::Player(3))
+        ));
+        // other players have their own budget
+        assert!(check(&mut world, default(), 2, 2).is_ok());
+        // props not spawned yet count too
+        let pending = HashMap::from([(ClientId::from_raw(2), 3)]);
+        assert!(matches!(
+            check(&mut world, pending, 2, 2),
+            Err(BudgetExceeded::Player(3))
+        ));
+    }
+
+    #[test]
+    fn world_over_budget_is_refused() {
+        let mut world = World::new();
+        for owner in 0..8 {
+            world.spawn(prop(owner, 1));
+        }
+        assert!(check(&mut world, default(), 1, 2).is_ok());
+        assert!(matches!(
+            check(&mut world, default(), 1, 3),
+            Err(BudgetExceeded::World(8))
+        ));
+    }
+
+    #[test]
+    fn props_decay_after_their_lifetime() {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<ServerMetrics>();
+        let old = world
+            .spawn(Prop {
+                expires_at: Duration::from_secs(5),
+                ..prop(1, 1)
+            })
+            .id();
+        let new = world
+            .spawn(Prop {
+                expires_at: Duration::from_secs(15),
+                ..prop(1, 2)
+            })
+            .id();
+
+        world.run_system_once(decay_props);
+        assert!(world.get_entity(old).is_some());
+
+        world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs(10));
+        world.run_system_once(decay_props);
+        assert!(world.get_entity(old).is_none());
+        assert!(world.get_entity(new).is_some());
+        assert_eq!(world.resource::<ServerMetrics>().prop_decays, 1);
+    }
+
+    #[test]
+    fn undo_takes_only_the_callers_last_spawn() {
+        let mut world = World::new();
+        let first = world.spawn(prop(1, 1)).id();
+        let last = [world.spawn(prop(1, 2)).id(), world.spawn(prop(1, 2)).id()];
+        // a later spawn by someone else
+        world.spawn(prop(2, 3));
+        // a loaded prop whose owner hasn't joined yet
+        world.spawn(Prop {
+            owner: None,
+            ..prop(1, 4)
+        });
+
+        let mut undone = world.run_system_once(|props: Query<(Entity, &Prop)>| {
+            last_spawn(&props, ClientId::from_raw(1))
+        });
+        undone.sort();
+        let mut expected = last.to_vec();
+        expected.sort();
+        assert_eq!(undone, expected);
+        assert!(!undone.contains(&first));
+
+        let nothing = world.run_system_once(|props: Query<(Entity, &Prop)>| {
+            last_spawn(&props, ClientId::from_raw(3))
+        });
+        assert!(nothing.is_empty());
+    }
+}
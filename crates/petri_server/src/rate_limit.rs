@@ -0,0 +1,186 @@
+use std::{marker::PhantomData, time::Duration};
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    utils::{get_short_name, HashMap},
+};
+use bevy_replicon::{
+    prelude::*,
+    renet::{ClientId, ServerEvent},
+};
+use petri_shared::{AdminCommand, ChatMessage, InputBatch, SetName, INPUT_BATCHES_PER_SECOND};
+
+use crate::metrics::ServerMetrics;
+
+/// Limits how often each client can send each kind of event
+pub struct RateLimitPlugin;
+
+impl Plugin for RateLimitPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // every wall is a lot of physics bodies
+            .rate_limit_client_event::<AdminCommand>(RateLimit::new(3.0, 0.5))
+            .rate_limit_client_event::<ChatMessage>(RateLimit::new(5.0, 0.5))
+            .rate_limit_client_event::<SetName>(RateLimit::new(3.0, 0.1))
+            // a little above what clients send, so only lag bursts get dropped, and
+            // every batch repeats the latest frames so a dropped one loses nothing.
+            // Clients sending far more are kicked by input validation.
+            .rate_limit_client_event::<InputBatch>(RateLimit::new(
+                INPUT_BATCHES_PER_SECOND / 2.0,
+                INPUT_BATCHES_PER_SECOND * 1.5,
+            ));
+    }
+}
+
+/// Token bucket configuration
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// How many events can be sent in a quick burst
+    pub burst: f32,
+    /// How many events per second can be sent after the burst
+    pub per_second: f32,
+}
+
+impl RateLimit {
+    pub fn new(burst: f32, per_second: f32) -> Self {
+        Self { burst, per_second }
+    }
+}
+
+pub trait RateLimitAppExt {
+    /// Makes [`RateLimitedEvents<T>`] drop events of clients that exceed `limit`
+    fn rate_limit_client_event<T: Event>(&mut self, limit: RateLimit) -> &mut Self;
+}
+
+impl RateLimitAppExt for App {
+    fn rate_limit_client_event<T: Event>(&mut self, limit: RateLimit) -> &mut Self {
+        self.insert_resource(RateLimiter::<T> {
+            limit,
+            buckets: default(),
+            marker: PhantomData,
+        })
+        .add_systems(Update, forget_disconnected_clients::<T>)
+    }
+}
+
+#[derive(Resource)]
+struct RateLimiter<T> {
+    limit: RateLimit,
+    buckets: HashMap<ClientId, Bucket>,
+    marker: PhantomData<T>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f32,
+    refilled_at: Duration,
+}
+
+impl<T> RateLimiter<T> {
+    fn try_take(&mut self, client_id: ClientId, now: Duration) -> bool {
+        let limit = self.limit;
+        let bucket = self.buckets.entry(client_id).or_insert(Bucket {
+            tokens: limit.burst,
+            refilled_at: now,
+        });
+        let refill = (now - bucket.refilled_at).as_secs_f32() * limit.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(limit.burst);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Reads [`FromClient<T>`] like an [`EventReader`], but skips events
+/// of clients that have exceeded the configured [`RateLimit`].
+///
+/// Every event that is read spends the client's allowance,
+/// so there should be only one system reading each event type this way.
+#[derive(SystemParam)]
+pub struct RateLimitedEvents<'w, 's, T: Event> {
+    reader: EventReader<'w, 's, FromClient<T>>,
+    limiter: ResMut<'w, RateLimiter<T>>,
+    metrics: ResMut<'w, ServerMetrics>,
+    time: Res<'w, Time>,
+}
+
+impl<'w, 's, T: Event> RateLimitedEvents<'w, 's, T> {
+    pub fn read(&mut self) -> impl Iterator<Item = &FromClient<T>> {
+        let now = self.time.elapsed();
+        let limiter = &mut *self.limiter;
+        let metrics = &mut *self.metrics;
+        self.reader.read().filter(move |event| {
+            let allowed = limiter.try_take(event.client_id, now);
+            if !allowed {
+                let event_name = get_short_name(std::any::type_name::<T>());
+                debug!("Client {} is rate limited on {event_name}", event.client_id);
+                metrics.count_rate_limited(event_name);
+            }
+            allowed
+        })
+    }
+}
+
+fn forget_disconnected_clients<T: Event>(
+    mut server_events: EventReader<ServerEvent>,
+    mut limiter: ResMut<RateLimiter<T>>,
+) {
+    for event in server_events.read() {
+        if let ServerEvent::ClientDisconnected { client_id, .. } = event {
+            limiter.buckets.remove(client_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(burst: f32, per_second: f32) -> RateLimiter<ChatMessage> {
+        RateLimiter {
+            limit: RateLimit::new(burst, per_second),
+            buckets: default(),
+            marker: PhantomData,
+        }
+    }
+
+    #[test]
+    fn burst_then_refuses() {
+        let mut limiter = limiter(3.0, 1.0);
+        let client = ClientId::from_raw(1);
+        for _ in 0..3 {
+            assert!(limiter.try_take(client, Duration::ZERO));
+        }
+        assert!(!limiter.try_take(client, Duration::ZERO));
+    }
+
+    #[test]
+    fn refills_over_time_up_to_the_burst() {
+        let mut limiter = limiter(2.0, 2.0);
+        let client = ClientId::from_raw(1);
+        assert!(limiter.try_take(client, Duration::ZERO));
+        assert!(limiter.try_take(client, Duration::ZERO));
+        assert!(!limiter.try_take(client, Duration::from_millis(100)));
+        assert!(limiter.try_take(client, Duration::from_millis(600)));
+
+        // a long pause refills only the burst
+        let later = Duration::from_secs(60);
+        assert!(limiter.try_take(client, later));
+        assert!(limiter.try_take(client, later));
+        assert!(!limiter.try_take(client, later));
+    }
+
+    #[test]
+    fn clients_have_their_own_buckets() {
+        let mut limiter = limiter(1.0, 0.1);
+        assert!(limiter.try_take(ClientId::from_raw(1), Duration::ZERO));
+        assert!(!limiter.try_take(ClientId::from_raw(1), Duration::ZERO));
+        assert!(limiter.try_take(ClientId::from_raw(2), Duration::ZERO));
+    }
+}
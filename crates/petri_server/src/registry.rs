@@ -0,0 +1,90 @@
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+use bevy::{app::AppExit, prelude::*, time::common_conditions::on_timer};
+use bevy_replicon::prelude::*;
+use petri_shared::registry::{registry_address, RegistryRequest, HEARTBEAT_INTERVAL, MAX_HOST_LEN};
+
+use crate::discovery::{server_info, ServerName};
+
+/// Keeps this server on the list of `petri_registry`
+pub struct RegistryPlugin;
+
+impl Plugin for RegistryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, open_registry_socket.map(bevy::utils::error))
+            .add_systems(
+                Update,
+                // right away too, or new servers would only be listed after the first interval
+                send_heartbeat.run_if(resource_exists::<RegistrySocket>.and_then(
+                    on_timer(HEARTBEAT_INTERVAL).or_else(resource_added::<RegistrySocket>),
+                )),
+            )
+            .add_systems(
+                Last,
+                say_goodbye
+                    .run_if(resource_exists::<RegistrySocket>.and_then(on_event::<AppExit>())),
+            );
+    }
+}
+
+#[derive(Resource)]
+struct RegistrySocket {
+    socket: UdpSocket,
+    registry: SocketAddr,
+    /// Can be set with the `PETRI_PUBLIC_HOST` environment variable
+    /// when clients can't reach the server at the address its heartbeats come from
+    public_host: Option<String>,
+}
+
+fn open_registry_socket(mut commands: Commands) -> anyhow::Result<()> {
+    let public_host = std::env::var("PETRI_PUBLIC_HOST").ok();
+    if let Some(host) = &public_host {
+        // the registry ignores heartbeats with longer hosts, the server would never be listed
+        anyhow::ensure!(
+            host.len() <= MAX_HOST_LEN,
+            "PETRI_PUBLIC_HOST is {} bytes long, the registry accepts at most {MAX_HOST_LEN}",
+            host.len()
+        );
+    }
+
+    let address = registry_address();
+    // the socket is IPv4, and `localhost` may resolve to `::1` first
+    let registry = address
+        .to_socket_addrs()?
+        .find(SocketAddr::is_ipv4)
+        .ok_or_else(|| anyhow::anyhow!("{address} has no IPv4 addresses"))?;
+    info!("Sending heartbeats to the registry at {registry}");
+
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_nonblocking(true)?;
+    commands.insert_resource(RegistrySocket {
+        socket,
+        registry,
+        public_host,
+    });
+    Ok(())
+}
+
+fn send_heartbeat(registry: Res<RegistrySocket>, name: Res<ServerName>, server: Res<RenetServer>) {
+    send(
+        &registry,
+        RegistryRequest::Heartbeat {
+            info: server_info(&name, &server),
+            public_host: registry.public_host.clone(),
+        },
+    );
+}
+
+fn say_goodbye(registry: Res<RegistrySocket>) {
+    send(&registry, RegistryRequest::Goodbye);
+}
+
+fn send(registry: &RegistrySocket, request: RegistryRequest) {
+    if let Err(e) = registry
+        .socket
+        .send_to(&request.to_bytes(), registry.registry)
+    {
+        // a registry that isn't running doesn't fail the send, so this is worth knowing
+        warn!("Could not reach the registry at {}: {e}", registry.registry);
+    }
+}
@@ -0,0 +1,482 @@
+//! Saving the world to disk, so that it survives server restarts.
+//!
+//! Entities marked [`Persistent`] are saved together with the last known
+//! position of every named player. Players get their position back
+//! when they join with the same name.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{
+    app::AppExit, prelude::*, scene::ron, time::common_conditions::on_timer, utils::HashMap,
+};
+use bevy_rapier3d::prelude::*;
+use bevy_replicon::renet::{ClientId, ServerEvent};
+use petri_shared::{
+    get_player_capsule_size, Appearance, Player, PlayerStats, ReplicationBundle, Tint,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    plugin::{PhysicsBundle, PlayerMap},
+    props::{Prop, SpawnCounter},
+};
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        let path = std::env::var("PETRI_SAVE_FILE").unwrap_or_else(|_| DEFAULT_SAVE_FILE.into());
+        app.insert_resource(SaveFile(path.into()))
+            .init_resource::<SavedPlayers>()
+            .init_resource::<OwnerNames>()
+            .add_systems(Startup, load_world.map(Result::unwrap))
+            .add_systems(
+                Update,
+                (restore_players, claim_props, remember_leaving_players),
+            )
+            .add_systems(
+                Last,
+                (
+                    save_world
+                        .map(bevy::utils::error)
+                        .run_if(on_timer(AUTOSAVE_INTERVAL)),
+                    save_world
+                        .map(exit_if_not_saved)
+                        .run_if(on_event::<AppExit>()),
+                ),
+            );
+    }
+}
+
+/// Can be changed with the `PETRI_SAVE_FILE` environment variable
+const DEFAULT_SAVE_FILE: &str = "world.ron";
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Bump when the format of [`WorldSave`] changes
+const SAVE_VERSION: u32 = 2;
+
+/// Marks entities that are saved with the world
+#[derive(Component, Debug)]
+pub struct Persistent;
+
+#[derive(Resource, Debug)]
+struct SaveFile(PathBuf);
+
+/// Players that are not connected right now, by lowercase name
+#[derive(Resource, Default, Debug)]
+struct SavedPlayers(HashMap<String, SavedPlayer>);
+
+/// Lowercase names of the clients named since the server started.
+/// Client ids change with every connection, so props are saved by the name of their owner.
+#[derive(Resource, Default, Debug)]
+struct OwnerNames(HashMap<ClientId, String>);
+
+/// A loaded prop waiting for the player with this lowercase name to join
+#[derive(Component, Debug)]
+struct UnclaimedBy(String);
+
+/// Read first to find out how to read the rest of the file
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct WorldSave {
+    version: u32,
+    entities: Vec<SavedEntity>,
+    players: Vec<SavedPlayer>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct SavedEntity {
+    transform: Transform,
+    tint: Color,
+    appearance: Appearance,
+    name: Option<String>,
+    prop: Option<SavedProp>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct SavedProp {
+    /// Lowercase name, `None` if the owner never picked a name
+    owner: Option<String>,
+    spawn: u32,
+    /// Time left until the prop decays
+    remaining: Duration,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct SavedPlayer {
+    name: String,
+    transform: Transform,
+    deaths: u32,
+}
+
+fn load_world(
+    mut commands: Commands,
+    file: Res<SaveFile>,
+    mut saved_players: ResMut<SavedPlayers>,
+    mut spawn_counter: ResMut<SpawnCounter>,
+    time: Res<Time>,
+) -> anyhow::Result<()> {
+    let Some(save) = read_save(&file.0)? else {
+        info!("No save file at {:?}, starting a new world", file.0);
+        return Ok(());
+    };
+
+    info!(
+        "Loading {} entities and {} players from {:?}",
+        save.entities.len(),
+        save.players.len(),
+        file.0
+    );
+    for saved in save.entities {
+        let collider = match saved.appearance {
+            Appearance::Box => Collider::cuboid(0.5, 0.5, 0.5),
+            Appearance::Capsule | Appearance::Character(_) => {
+                let (diameter, segment_half_height) = get_player_capsule_size();
+                Collider::capsule_y(segment_half_height, diameter / 2.0)
+            }
+        };
+        let mut entity = commands.spawn((
+            Persistent,
+            PhysicsBundle {
+                collider,
+                trans: TransformBundle::from_transform(saved.transform),
+                ..default()
+            },
+            ReplicationBundle::new(Tint(saved.tint), saved.appearance),
+        ));
+        if let Some(name) = saved.name {
+            entity.insert(Name::new(name));
+        }
+        if let Some(prop) = saved.prop {
+            // new spawns must not be mistaken for loaded ones
+            spawn_counter.skip_past(prop.spawn);
+            entity.insert(Prop {
+                owner: None,
+                spawn: prop.spawn,
+                expires_at: time.elapsed() + prop.remaining,
+            });
+            if let Some(owner) = prop.owner {
+                entity.insert(UnclaimedBy(owner));
+            }
+        }
+    }
+    saved_players.0 = save
+        .players
+        .into_iter()
+        .map(|player| (player.name.to_lowercase(), player))
+        .collect();
+    Ok(())
+}
+
+/// `None` if there is no save yet
+fn read_save(path: &Path) -> anyhow::Result<Option<WorldSave>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let SaveHeader { version } = ron::from_str(&text)?;
+    // refuse to start rather than overwrite a save we don't understand
+    anyhow::ensure!(
+        version == SAVE_VERSION,
+        "Save file {path:?} has version {version}, expected {SAVE_VERSION}"
+    );
+    Ok(Some(ron::from_str(&text)?))
+}
+
+fn write_save(path: &Path, save: &WorldSave) -> anyhow::Result<()> {
+    let text = ron::ser::to_string_pretty(save, default())?;
+    // a crash while writing must not destroy the previous save
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, text)?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Puts players back where they were when they left
+fn restore_players(
+    mut named_players: Query<(&Name, &mut Transform, &mut PlayerStats), Added<Name>>,
+    mut saved_players: ResMut<SavedPlayers>,
+) {
+    for (name, mut transform, mut stats) in &mut named_players {
+        if let Some(saved) = saved_players.0.remove(&name.to_lowercase()) {
+            info!("Welcome back, {name}");
+            *transform = saved.transform;
+            stats.deaths = saved.deaths;
+        }
+    }
+}
+
+/// Gives props back to their owner when they join again, after a restart
+/// or just under the new client id of another connection
+fn claim_props(
+    mut commands: Commands,
+    named_players: Query<(&Player, &Name), Changed<Name>>,
+    mut props: Query<(Entity, &mut Prop, Option<&UnclaimedBy>)>,
+    mut owner_names: ResMut<OwnerNames>,
+    player_map: Res<PlayerMap>,
+) {
+    for (player, name) in &named_players {
+        let name = name.to_lowercase();
+        owner_names.0.insert(player.0, name.clone());
+        for (entity, mut prop, unclaimed) in &mut props {
+            let owned = match (prop.owner, unclaimed) {
+                (None, Some(unclaimed)) => unclaimed.0 == name,
+                (Some(owner), _) => {
+                    owner != player.0
+                        && !player_map.0.contains_key(&owner)
+                        && owner_names.0.get(&owner) == Some(&name)
+                }
+                (None, None) => false,
+            };
+            if owned {
+                prop.owner = Some(player.0);
+                commands.entity(entity).remove::<UnclaimedBy>();
+            }
+        }
+    }
+}
+
+fn remember_leaving_players(
+    mut server_events: EventReader<ServerEvent>,
+    players: Query<(&Player, &Name, &Transform, &PlayerStats)>,
+    mut saved_players: ResMut<SavedPlayers>,
+) {
+    for event in server_events.read() {
+        let ServerEvent::ClientDisconnected { client_id, .. } = event else {
+            continue;
+        };
+        // the player entity is despawned with commands, so it is still here
+        if let Some(player) = players
+            .iter()
+            .find(|(player, ..)| player.0 == *client_id)
+            .map(saved_player)
+        {
+            saved_players.0.insert(player.name.to_lowercase(), player);
+        }
+    }
+}
+
+fn saved_player(
+    (_, name, transform, stats): (&Player, &Name, &Transform, &PlayerStats),
+) -> SavedPlayer {
+    SavedPlayer {
+        name: name.to_string(),
+        transform: *transform,
+        deaths: stats.deaths,
+    }
+}
+
+/// The server is about to exit, so failing to save must not go unnoticed
+fn exit_if_not_saved(result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        error!("Could not save the world: {e:?}");
+        std::process::exit(1);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn save_world(
+    file: Res<SaveFile>,
+    entities: Query<
+        (
+            &Transform,
+            &Tint,
+            &Appearance,
+            Option<&Name>,
+            Option<(&Prop, Option<&UnclaimedBy>)>,
+        ),
+        With<Persistent>,
+    >,
+    players: Query<(&Player, &Name, &Transform, &PlayerStats)>,
+    saved_players: Res<SavedPlayers>,
+    owner_names: Res<OwnerNames>,
+    time: Res<Time>,
+) -> anyhow::Result<()> {
+    let entities = entities
+        .iter()
+        .map(|(transform, tint, appearance, name, prop)| SavedEntity {
+            transform: *transform,
+            tint: tint.0,
+            appearance: appearance.clone(),
+            name: name.map(|n| n.to_string()),
+            prop: prop.map(|(prop, unclaimed)| SavedProp {
+                owner: match prop.owner {
+                    Some(owner) => owner_names.0.get(&owner).cloned(),
+                    None => unclaimed.map(|u| u.0.clone()),
+                },
+                spawn: prop.spawn,
+                remaining: prop.expires_at.saturating_sub(time.elapsed()),
+            }),
+        })
+        .collect();
+    let players = saved_players
+        .0
+        .values()
+        .cloned()
+        .chain(players.iter().map(saved_player))
+        .collect();
+    let save = WorldSave {
+        version: SAVE_VERSION,
+        entities,
+        players,
+    };
+    write_save(&file.0, &save)?;
+    debug!(
+        "Saved {} entities and {} players to {:?}",
+        save.entities.len(),
+        save.players.len(),
+        file.0
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn prop(owner: &str, spawn: u32) -> SavedEntity {
+        SavedEntity {
+            transform: Transform::from_xyz(1.0, 2.0, 3.0),
+            tint: Color::GREEN,
+            appearance: Appearance::Box,
+            name: None,
+            prop: Some(SavedProp {
+                owner: Some(owner.to_owned()),
+                spawn,
+                remaining: Duration::from_secs(30),
+            }),
+        }
+    }
+
+    fn world_save() -> WorldSave {
+        WorldSave {
+            version: SAVE_VERSION,
+            entities: vec![
+                prop("bob", 7),
+                SavedEntity {
+                    transform: Transform::from_xyz(-4.0, 0.5, 8.0)
+                        .with_rotation(Quat::from_rotation_y(1.0)),
+                    tint: Color::ORANGE,
+                    appearance: Appearance::Character("characters/monster.glb".to_owned()),
+                    name: Some("Monster".to_owned()),
+                    prop: None,
+                },
+            ],
+            players: vec![SavedPlayer {
+                name: "Bob".to_owned(),
+                transform: Transform::from_xyz(0.0, 1.0, -2.0),
+                deaths: 3,
+            }],
+        }
+    }
+
+    #[test]
+    fn saved_world_loads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("world.ron");
+        let save = world_save();
+        write_save(&path, &save).unwrap();
+        assert_eq!(read_save(&path).unwrap(), Some(save));
+    }
+
+    #[test]
+    fn missing_save_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(read_save(&dir.path().join("missing.ron")).unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_saves_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.ron");
+        fs::write(&path, "(version: 1, entities: [").unwrap();
+        assert!(read_save(&path).is_err());
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("old-world.ron");
+        let save = WorldSave {
+            version: SAVE_VERSION + 1,
+            ..world_save()
+        };
+        write_save(&path, &save).unwrap();
+        assert!(read_save(&path).is_err());
+    }
+
+    #[test]
+    fn new_spawns_come_after_loaded_props() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("props.ron");
+        let save = WorldSave {
+            entities: vec![prop("bob", 7), prop("alice", 3)],
+            ..world_save()
+        };
+        write_save(&path, &save).unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(SaveFile(path));
+        world.init_resource::<SavedPlayers>();
+        world.init_resource::<SpawnCounter>();
+        world.init_resource::<Time>();
+        world.run_system_once(load_world).unwrap();
+
+        assert_eq!(world.resource_mut::<SpawnCounter>().next(), 8);
+        let mut owners: Vec<_> = world
+            .query::<(&Prop, &UnclaimedBy)>()
+            .iter(&world)
+            .map(|(prop, unclaimed)| (prop.owner, unclaimed.0.clone()))
+            .collect();
+        owners.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            owners,
+            [(None, "alice".to_owned()), (None, "bob".to_owned())]
+        );
+        assert!(world.resource::<SavedPlayers>().0.contains_key("bob"));
+    }
+
+    #[test]
+    fn players_claim_their_props_by_name() {
+        let mut world = World::new();
+        world.init_resource::<OwnerNames>();
+        world.init_resource::<PlayerMap>();
+        let prop = |owner| Prop {
+            owner,
+            spawn: 1,
+            expires_at: Duration::MAX,
+        };
+        let loaded = world
+            .spawn((prop(None), UnclaimedBy("bob".to_owned())))
+            .id();
+        let others = world
+            .spawn((prop(None), UnclaimedBy("alice".to_owned())))
+            .id();
+
+        // bob joins, leaves and comes back with a new client id
+        let first = ClientId::from_raw(1);
+        let player = world.spawn((Player(first), Name::new("Bob"))).id();
+        world.run_system_once(claim_props);
+        assert_eq!(world.get::<Prop>(loaded).unwrap().owner, Some(first));
+        assert!(world.get::<UnclaimedBy>(loaded).is_none());
+        assert_eq!(world.get::<Prop>(others).unwrap().owner, None);
+
+        world.despawn(player);
+        let second = ClientId::from_raw(2);
+        let player = world.spawn((Player(second), Name::new("bob"))).id();
+        world.resource_mut::<PlayerMap>().0.insert(second, player);
+        world.run_system_once(claim_props);
+        assert_eq!(world.get::<Prop>(loaded).unwrap().owner, Some(second));
+    }
+}
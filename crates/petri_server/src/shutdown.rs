@@ -0,0 +1,112 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bevy::{app::AppExit, prelude::*};
+use bevy_replicon::prelude::*;
+use petri_shared::ServerShutdown;
+
+/// Shuts the server down on SIGINT or SIGTERM, giving the players a countdown first.
+///
+/// The world is saved by [`crate::save::SavePlugin`] when [`AppExit`] is sent.
+pub struct ShutdownPlugin;
+
+impl Plugin for ShutdownPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Shutdown>()
+            .add_systems(Startup, listen_for_signals.map(Result::unwrap))
+            .add_systems(Update, shut_down);
+    }
+}
+
+const SHUTDOWN_COUNTDOWN: Duration = Duration::from_secs(5);
+/// Time for the disconnect packets to reach the clients before the process exits
+const DISCONNECT_GRACE: Duration = Duration::from_millis(500);
+/// Exit status when the operator insists on quitting right away, as if killed by SIGINT
+const FORCED_EXIT_STATUS: i32 = 130;
+
+/// How many termination signals have been received
+#[derive(Resource)]
+struct Signals(Arc<AtomicUsize>);
+
+#[derive(Resource, Default, Debug)]
+enum Shutdown {
+    #[default]
+    Running,
+    Countdown {
+        ends_at: Duration,
+        seconds_announced: Option<u32>,
+    },
+    Disconnecting {
+        exit_at: Duration,
+    },
+}
+
+fn listen_for_signals(mut commands: Commands) -> anyhow::Result<()> {
+    let signals = Arc::new(AtomicUsize::new(0));
+    let received = signals.clone();
+    ctrlc::set_handler(move || {
+        if received.fetch_add(1, Ordering::SeqCst) > 0 {
+            warn!("Received a second signal, exiting without saving");
+            std::process::exit(FORCED_EXIT_STATUS);
+        }
+    })?;
+    commands.insert_resource(Signals(signals));
+    Ok(())
+}
+
+fn shut_down(
+    signals: Res<Signals>,
+    mut shutdown: ResMut<Shutdown>,
+    mut server: ResMut<RenetServer>,
+    mut notices: EventWriter<ToClients<ServerShutdown>>,
+    mut exit: EventWriter<AppExit>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    match &mut *shutdown {
+        Shutdown::Running => {
+            if signals.0.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            if server.clients_id().is_empty() {
+                info!("Shutting down");
+                *shutdown = Shutdown::Disconnecting { exit_at: now };
+            } else {
+                info!("Shutting down in {SHUTDOWN_COUNTDOWN:?}, send the signal again to quit now");
+                *shutdown = Shutdown::Countdown {
+                    ends_at: now + SHUTDOWN_COUNTDOWN,
+                    seconds_announced: None,
+                };
+            }
+        }
+        Shutdown::Countdown {
+            ends_at,
+            seconds_announced,
+        } => {
+            let seconds_left = ends_at.saturating_sub(now).as_secs_f32().ceil() as u32;
+            if seconds_left == 0 {
+                info!("Disconnecting {} clients", server.clients_id().len());
+                server.disconnect_all();
+                *shutdown = Shutdown::Disconnecting {
+                    exit_at: now + DISCONNECT_GRACE,
+                };
+            } else if *seconds_announced != Some(seconds_left) {
+                *seconds_announced = Some(seconds_left);
+                notices.send(ToClients {
+                    mode: SendMode::Broadcast,
+                    event: ServerShutdown { seconds_left },
+                });
+            }
+        }
+        Shutdown::Disconnecting { exit_at } => {
+            if now >= *exit_at {
+                exit.send(AppExit);
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, time::common_conditions::on_timer};
+use bevy_replicon::prelude::*;
+use petri_shared::{NetworkQuality, Player, PlayerStats};
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                add_stats_to_new_players,
+                update_player_stats.run_if(on_timer(Duration::from_secs(1))),
+            ),
+        );
+    }
+}
+
+/// When the player has connected, in server time
+#[derive(Component)]
+struct ConnectedAt(Duration);
+
+fn add_stats_to_new_players(
+    mut commands: Commands,
+    new_players: Query<Entity, Added<Player>>,
+    time: Res<Time>,
+) {
+    for entity in &new_players {
+        commands.entity(entity).insert((
+            PlayerStats::default(),
+            NetworkQuality::default(),
+            ConnectedAt(time.elapsed()),
+        ));
+    }
+}
+
+fn update_player_stats(
+    mut players: Query<(&Player, &ConnectedAt, &mut PlayerStats, &mut NetworkQuality)>,
+    server: Res<RenetServer>,
+    time: Res<Time>,
+) {
+    for (Player(client_id), ConnectedAt(connected_at), mut stats, mut quality) in &mut players {
+        if let Ok(info) = server.network_info(*client_id) {
+            stats.ping_ms = (info.rtt * 1000.0) as u32;
+            *quality = NetworkQuality {
+                rtt_ms: (info.rtt * 1000.0) as f32,
+                packet_loss: info.packet_loss as f32,
+                sent_kbps: (info.bytes_sent_per_second * 8.0 / 1000.0) as f32,
+                received_kbps: (info.bytes_received_per_second * 8.0 / 1000.0) as f32,
+            };
+        }
+        stats.connected_secs = (time.elapsed() - *connected_at).as_secs() as u32;
+    }
+}
@@ -0,0 +1,194 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::{prelude::*, utils::HashMap};
+use bevy_replicon::{
+    prelude::*,
+    renet::{ClientId, ServerEvent},
+};
+use petri_shared::{DisconnectNotice, InputBatch, InputFrame, INPUT_BATCHES_PER_SECOND};
+use thiserror::Error;
+
+use crate::kick::Kick;
+
+/// Checks client input and kicks clients that keep sending garbage
+pub struct ValidationPlugin;
+
+impl Plugin for ValidationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ValidationSettings>()
+            .init_resource::<Offenders>()
+            .init_resource::<InputRates>()
+            .add_event::<InputViolation>()
+            .add_systems(
+                Update,
+                (detect_input_flooding, punish_offenders, forget_offenders),
+            );
+    }
+}
+
+#[derive(Resource, Debug)]
+pub struct ValidationSettings {
+    /// Input batches a client may send per second, on average over a second.
+    /// Above the rate limit of [`InputBatch`]es, so only clients sending far more
+    /// than [`INPUT_BATCHES_PER_SECOND`] are kicked.
+    pub max_batches_per_second: f32,
+    /// Turning faster than this between two input frames is suspicious
+    pub max_aim_turn_per_frame: f32,
+    pub max_wall_side: u8,
+    /// How far from the player things can be built
+    pub max_build_distance: f32,
+    /// A client is kicked after this many violations within `violation_window`
+    pub kick_after_violations: usize,
+    pub violation_window: Duration,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            max_batches_per_second: INPUT_BATCHES_PER_SECOND * 2.0,
+            max_aim_turn_per_frame: 150f32.to_radians(),
+            max_wall_side: 10,
+            max_build_distance: 20.0,
+            kick_after_violations: 10,
+            violation_window: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Violation {
+    #[error("movement {0} is not a finite vector")]
+    InvalidMovement(Vec2),
+    #[error("aim {0} is not a unit vector")]
+    InvalidAim(Vec3),
+    #[error("aim snapped by {:.0} degrees in one frame", .0.to_degrees())]
+    AimSnap(f32),
+    #[error("sent {0} input frames in one batch")]
+    OversizedBatch(usize),
+    #[error("input sequence jumped from {from} to {to}")]
+    SequenceJump { from: u32, to: u32 },
+    #[error("sent {batches} input batches in {seconds:.1} s")]
+    InputFlooding { batches: u32, seconds: f32 },
+    #[error("tried to build at {0}, too far away")]
+    BuildTooFar(Vec3),
+    #[error("tried to build a wall of size {0}")]
+    WallTooBig(u8),
+}
+
+/// Sent by the systems that handle client input when they find something wrong with it
+#[derive(Event, Debug)]
+pub struct InputViolation {
+    pub client_id: ClientId,
+    pub violation: Violation,
+}
+
+/// Checks that the frame makes sense and clamps the movement to unit length
+pub(crate) fn validate_frame(frame: &InputFrame) -> Result<InputFrame, Violation> {
+    let aim = *frame.aim;
+    if !aim.is_finite() || !aim.is_normalized() {
+        return Err(Violation::InvalidAim(aim));
+    }
+    let movement = match frame.movement {
+        Some(movement) if !movement.is_finite() => {
+            return Err(Violation::InvalidMovement(movement))
+        }
+        movement => movement.map(|m| m.clamp_length_max(1.0)),
+    };
+    Ok(InputFrame {
+        sequence: frame.sequence,
+        movement,
+        aim: frame.aim,
+    })
+}
+
+/// Times of the recent violations of each client
+#[derive(Resource, Default, Debug)]
+struct Offenders(HashMap<ClientId, VecDeque<Duration>>);
+
+/// Input batches received from each client since the window started
+#[derive(Resource, Default, Debug)]
+struct InputRates {
+    batches: HashMap<ClientId, u32>,
+    window_started: Duration,
+}
+
+fn detect_input_flooding(
+    mut events: EventReader<FromClient<InputBatch>>,
+    mut rates: ResMut<InputRates>,
+    mut violations: EventWriter<InputViolation>,
+    settings: Res<ValidationSettings>,
+    time: Res<Time<Real>>,
+) {
+    for FromClient { client_id, .. } in events.read() {
+        *rates.batches.entry(*client_id).or_default() += 1;
+    }
+
+    // wall clock time, however fast the server manages to tick
+    let seconds = (time.elapsed() - rates.window_started).as_secs_f32();
+    if seconds < 1.0 {
+        return;
+    }
+    for (client_id, batches) in rates.batches.drain() {
+        if batches as f32 > seconds * settings.max_batches_per_second {
+            violations.send(InputViolation {
+                client_id,
+                violation: Violation::InputFlooding { batches, seconds },
+            });
+        }
+    }
+    rates.window_started = time.elapsed();
+}
+
+fn punish_offenders(
+    mut violations: EventReader<InputViolation>,
+    mut offenders: ResMut<Offenders>,
+    mut kicks: EventWriter<Kick>,
+    settings: Res<ValidationSettings>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed();
+    for InputViolation {
+        client_id,
+        violation,
+    } in violations.read()
+    {
+        warn!("Client {client_id}: {violation}");
+
+        let recent = offenders.0.entry(*client_id).or_default();
+        recent.push_back(now);
+        while recent
+            .front()
+            .is_some_and(|t| now - *t > settings.violation_window)
+        {
+            recent.pop_front();
+        }
+
+        if recent.len() >= settings.kick_after_violations {
+            warn!(
+                "Kicking client {client_id}: {} violations in {:?}",
+                recent.len(),
+                settings.violation_window
+            );
+            recent.clear();
+            kicks.send(Kick {
+                client_id: *client_id,
+                notice: DisconnectNotice::Kicked(format!(
+                    "Too many invalid inputs, last: {violation}"
+                )),
+            });
+        }
+    }
+}
+
+fn forget_offenders(
+    mut server_events: EventReader<ServerEvent>,
+    mut offenders: ResMut<Offenders>,
+    mut rates: ResMut<InputRates>,
+) {
+    for event in server_events.read() {
+        if let ServerEvent::ClientDisconnected { client_id, .. } = event {
+            offenders.0.remove(client_id);
+            rates.batches.remove(client_id);
+        }
+    }
+}
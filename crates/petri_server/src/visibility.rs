@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, time::common_conditions::on_timer};
+use bevy_rapier3d::prelude::*;
+use bevy_replicon::prelude::*;
+use petri_shared::{Player, NEAR_RELEVANCE_RADIUS, PLAYER_HEIGHT};
+
+use crate::plugin::PlayerMap;
+
+/// Decides which replicated entities are sent to which client.
+/// Requires [`VisibilityPolicy::Whitelist`] in the [`ServerPlugin`].
+pub struct VisibilityPlugin;
+
+impl Plugin for VisibilityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RelevanceSettings>().add_systems(
+            Update,
+            update_client_visibility.run_if(on_timer(Duration::from_millis(200))),
+        );
+    }
+}
+
+/// Marks entities that are replicated to every client regardless of distance
+#[derive(Component)]
+pub struct AlwaysRelevant;
+
+#[derive(Resource, Debug)]
+pub struct RelevanceSettings {
+    /// Everything closer than this is relevant, even behind walls
+    pub near_radius: f32,
+    /// Entities in line of sight are relevant up to this distance
+    pub far_radius: f32,
+    /// Once relevant, entities stay relevant until `far_radius * hysteresis`,
+    /// so they don't flicker in and out at the border
+    pub hysteresis: f32,
+}
+
+impl Default for RelevanceSettings {
+    fn default() -> Self {
+        Self {
+            near_radius: NEAR_RELEVANCE_RADIUS,
+            far_radius: 80.0,
+            hysteresis: 1.2,
+        }
+    }
+}
+
+fn update_client_visibility(
+    mut client_cache: ResMut<ClientCache>,
+    player_map: Res<PlayerMap>,
+    viewers: Query<&GlobalTransform, With<Player>>,
+    replicated: Query<(Entity, &GlobalTransform, Has<AlwaysRelevant>), With<Replication>>,
+    rapier: Res<RapierContext>,
+    settings: Res<RelevanceSettings>,
+) {
+    for client in client_cache.iter_mut() {
+        let Some(viewer) = player_map.0.get(&client.id()).copied() else {
+            continue;
+        };
+        let Ok(viewer_transform) = viewers.get(viewer) else {
+            continue;
+        };
+        let eyes = viewer_transform.translation() + Vec3::Y * PLAYER_HEIGHT;
+
+        let visibility = client.visibility_mut();
+        for (entity, transform, always_relevant) in &replicated {
+            let was_visible = visibility.is_visible(entity);
+            let to_target = transform.translation() - eyes;
+            let distance = to_target.length();
+
+            let relevant = always_relevant
+                || entity == viewer
+                || distance < settings.near_radius
+                || if was_visible {
+                    distance < settings.far_radius * settings.hysteresis
+                } else {
+                    distance < settings.far_radius
+                        && in_line_of_sight(&rapier, eyes, to_target, viewer, entity)
+                };
+
+            if relevant != was_visible {
+                visibility.set_visibility(entity, relevant);
+            }
+        }
+    }
+}
+
+/// Whether nothing but the target itself is hit when looking at it
+fn in_line_of_sight(
+    rapier: &RapierContext,
+    eyes: Vec3,
+    to_target: Vec3,
+    viewer: Entity,
+    target: Entity,
+) -> bool {
+    let filter = QueryFilter::default().exclude_collider(viewer);
+    match rapier.cast_ray(eyes, to_target, 1.0, true, filter) {
+        Some((hit, _)) => hit == target,
+        None => true,
+    }
+}
@@ -0,0 +1,273 @@
+//! Compact network encoding for the components that are replicated the most.
+//!
+//! Integers are written with bincode's varint encoding,
+//! so small values (like an identity rotation) take a single byte.
+
+use std::{f32::consts::FRAC_1_SQRT_2, io::Cursor};
+
+use bevy::{prelude::*, ptr::Ptr};
+use bevy_replicon::{
+    bincode::{self, DefaultOptions, Options},
+    prelude::*,
+};
+
+use crate::{ReplicatedAim, ReplicatedPos};
+
+/// Positions are rounded to this many steps per meter
+const POSITION_STEPS_PER_METER: f32 = 1024.0;
+/// Bits per each of the three smallest quaternion components
+const ROTATION_COMPONENT_BITS: u32 = 10;
+/// Bits per each of the two octahedral direction coordinates
+const DIRECTION_COMPONENT_BITS: u32 = 16;
+
+pub(crate) fn serialize_pos(component: Ptr, cursor: &mut Cursor<Vec<u8>>) -> bincode::Result<()> {
+    // SAFETY: Function is registered for `ReplicatedPos` only.
+    let pos: &ReplicatedPos = unsafe { component.deref() };
+    let (_, rotation, translation) = pos.0.to_scale_rotation_translation();
+    DefaultOptions::new().serialize_into(
+        cursor,
+        &(encode_position(translation), encode_rotation(rotation)),
+    )
+}
+
+/// Scale is not sent, replicated entities are expected to have a scale of one
+pub(crate) fn deserialize_pos(
+    entity: &mut EntityWorldMut,
+    _entity_map: &mut ServerEntityMap,
+    cursor: &mut Cursor<&[u8]>,
+    _replicon_tick: RepliconTick,
+) -> bincode::Result<()> {
+    let (position, rotation): ([i32; 3], u32) = DefaultOptions::new().deserialize_from(cursor)?;
+    let transform = Transform {
+        translation: decode_position(position),
+        rotation: decode_rotation(rotation),
+        scale: Vec3::ONE,
+    };
+    entity.insert(ReplicatedPos(transform.into()));
+    Ok(())
+}
+
+pub(crate) fn serialize_aim(component: Ptr, cursor: &mut Cursor<Vec<u8>>) -> bincode::Result<()> {
+    // SAFETY: Function is registered for `ReplicatedAim` only.
+    let aim: &ReplicatedAim = unsafe { component.deref() };
+    DefaultOptions::new().serialize_into(cursor, &encode_direction(aim.0))
+}
+
+pub(crate) fn deserialize_aim(
+    entity: &mut EntityWorldMut,
+    _entity_map: &mut ServerEntityMap,
+    cursor: &mut Cursor<&[u8]>,
+    _replicon_tick: RepliconTick,
+) -> bincode::Result<()> {
+    let direction: u32 = DefaultOptions::new().deserialize_from(cursor)?;
+    entity.insert(ReplicatedAim(decode_direction(direction)));
+    Ok(())
+}
+
+fn encode_position(position: Vec3) -> [i32; 3] {
+    (position * POSITION_STEPS_PER_METER)
+        .round()
+        .as_ivec3()
+        .to_array()
+}
+
+fn decode_position(position: [i32; 3]) -> Vec3 {
+    IVec3::from_array(position).as_vec3() / POSITION_STEPS_PER_METER
+}
+
+/// "Smallest three" encoding: the largest component is dropped
+/// and restored from the other three, since the quaternion is normalized.
+///
+/// The index of the dropped component is stored in the top two bits,
+/// counted from `w` so that the identity rotation encodes to zero.
+fn encode_rotation(rotation: Quat) -> u32 {
+    let components = rotation.normalize().to_array();
+    let largest = (0..4)
+        .max_by(|&a, &b| components[a].abs().total_cmp(&components[b].abs()))
+        .unwrap();
+    // q and -q are the same rotation, keep the dropped component positive
+    let sign = components[largest].signum();
+    let steps = ((1 << (ROTATION_COMPONENT_BITS - 1)) - 1) as f32;
+
+    let mut packed = (3 - largest as u32) << (3 * ROTATION_COMPONENT_BITS);
+    for (slot, component) in (0..4).filter(|&i| i != largest).enumerate() {
+        let quantized = (components[component] * sign / FRAC_1_SQRT_2 * steps).round() as i32;
+        packed |= zigzag(quantized) << (slot as u32 * ROTATION_COMPONENT_BITS);
+    }
+    packed
+}
+
+fn decode_rotation(packed: u32) -> Quat {
+    let largest = 3 - (packed >> (3 * ROTATION_COMPONENT_BITS)) as usize;
+    let steps = ((1 << (ROTATION_COMPONENT_BITS - 1)) - 1) as f32;
+    let mask = (1 << ROTATION_COMPONENT_BITS) - 1;
+
+    let mut components = [0.0; 4];
+    let mut sum_of_squares = 0.0;
+    for (slot, component) in (0..4).filter(|&i| i != largest).enumerate() {
+        let quantized = unzigzag((packed >> (slot as u32 * ROTATION_COMPONENT_BITS)) & mask);
+        let value = quantized as f32 / steps * FRAC_1_SQRT_2;
+        components[component] = value;
+        sum_of_squares += value * value;
+    }
+    components[largest] = (1.0 - sum_of_squares).max(0.0).sqrt();
+    Quat::from_array(components).normalize()
+}
+
+/// Octahedral encoding: the unit sphere is projected onto an octahedron,
+/// which is then unfolded into a square
+fn encode_direction(direction: Direction3d) -> u32 {
+    let n = *direction / (direction.x.abs() + direction.y.abs() + direction.z.abs());
+    let mut p = n.xy();
+    if n.z < 0.0 {
+        p = (Vec2::ONE - p.yx().abs()) * sign_not_zero(p);
+    }
+    let steps = ((1 << DIRECTION_COMPONENT_BITS) - 1) as f32;
+    let quantized = ((p * 0.5 + 0.5) * steps).round().as_uvec2();
+    quantized.x << DIRECTION_COMPONENT_BITS | quantized.y
+}
+
+fn decode_direction(packed: u32) -> Direction3d {
+    let steps = ((1 << DIRECTION_COMPONENT_BITS) - 1) as f32;
+    let mask = (1 << DIRECTION_COMPONENT_BITS) - 1;
+    let quantized = UVec2::new(packed >> DIRECTION_COMPONENT_BITS, packed & mask);
+    let p = quantized.as_vec2() / steps * 2.0 - 1.0;
+
+    let mut n = Vec3::new(p.x, p.y, 1.0 - p.x.abs() - p.y.abs());
+    let fold = (-n.z).max(0.0);
+    n.x -= fold * sign_not_zero(n.xy()).x;
+    n.y -= fold * sign_not_zero(n.xy()).y;
+    // the octahedron never passes through the origin, so this can't fail
+    Direction3d::new(n).unwrap()
+}
+
+fn sign_not_zero(v: Vec2) -> Vec2 {
+    Vec2::new(
+        if v.x >= 0.0 { 1.0 } else { -1.0 },
+        if v.y >= 0.0 { 1.0 } else { -1.0 },
+    )
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+fn unzigzag(value: u32) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic spread of unit vectors over the sphere
+    fn directions() -> impl Iterator<Item = Vec3> {
+        (0..40).flat_map(|i| {
+            (0..40).map(move |j| {
+                let polar = i as f32 / 39.0 * std::f32::consts::PI;
+                let azimuth = j as f32 / 40.0 * std::f32::consts::TAU;
+                Vec3::new(
+                    polar.sin() * azimuth.cos(),
+                    polar.cos(),
+                    polar.sin() * azimuth.sin(),
+                )
+            })
+        })
+    }
+
+    fn rotations() -> impl Iterator<Item = Quat> {
+        directions()
+            .zip((0..).map(|i| i as f32 * 0.37))
+            .map(|(axis, angle)| Quat::from_axis_angle(axis.normalize(), angle))
+            .chain([Quat::IDENTITY, -Quat::IDENTITY])
+    }
+
+    fn compact_size(
+        serialize: fn(Ptr, &mut Cursor<Vec<u8>>) -> bincode::Result<()>,
+        component: &impl Component,
+    ) -> usize {
+        let mut cursor = Cursor::new(Vec::new());
+        serialize(Ptr::from(component), &mut cursor).unwrap();
+        cursor.into_inner().len()
+    }
+
+    #[test]
+    fn position_round_trip() {
+        let max_error = 0.5 / POSITION_STEPS_PER_METER + f32::EPSILON * 1000.0;
+        for position in directions().map(|d| d * 123.4) {
+            let decoded = decode_position(encode_position(position));
+            assert!(
+                (decoded - position).abs().max_element() <= max_error,
+                "{position} became {decoded}"
+            );
+        }
+    }
+
+    #[test]
+    fn rotation_round_trip() {
+        for rotation in rotations() {
+            let decoded = decode_rotation(encode_rotation(rotation));
+            let error = rotation.angle_between(decoded);
+            assert!(
+                error < 0.005,
+                "{rotation} became {decoded}, {error} rad off"
+            );
+        }
+    }
+
+    #[test]
+    fn identity_rotation_is_zero() {
+        assert_eq!(encode_rotation(Quat::IDENTITY), 0);
+        assert_eq!(decode_rotation(0), Quat::IDENTITY);
+    }
+
+    #[test]
+    fn direction_round_trip() {
+        let axes = [
+            Vec3::X,
+            Vec3::Y,
+            Vec3::Z,
+            Vec3::NEG_X,
+            Vec3::NEG_Y,
+            Vec3::NEG_Z,
+        ];
+        for direction in directions().chain(axes) {
+            let direction = Direction3d::new(direction).unwrap();
+            let decoded = decode_direction(encode_direction(direction));
+            let error = direction.angle_between(*decoded);
+            assert!(
+                error < 0.001,
+                "{direction:?} became {decoded:?}, {error} rad off"
+            );
+        }
+    }
+
+    #[test]
+    fn compact_pos_is_smaller() {
+        let capsule = ReplicatedPos(Transform::from_xyz(3.2, 1.5, -7.9).into());
+        let tumbling_box = ReplicatedPos(
+            Transform::from_xyz(-120.3, 15.0, 42.1)
+                .with_rotation(Quat::from_euler(EulerRot::XYZ, 0.3, 1.2, -2.0))
+                .into(),
+        );
+
+        let default_size = DefaultOptions::new().serialized_size(&capsule).unwrap() as usize;
+        let capsule_size = compact_size(serialize_pos, &capsule);
+        let box_size = compact_size(serialize_pos, &tumbling_box);
+        // three 3-byte coordinates and a zero rotation
+        assert_eq!(capsule_size, 10);
+        assert!(
+            box_size * 2 < default_size,
+            "{box_size} bytes, {default_size} by default"
+        );
+    }
+
+    #[test]
+    fn compact_aim_is_smaller() {
+        let aim = ReplicatedAim(Direction3d::new(Vec3::new(0.3, -0.2, -1.0)).unwrap());
+        let default_size = DefaultOptions::new().serialized_size(&aim).unwrap() as usize;
+        let size = compact_size(serialize_aim, &aim);
+        assert!(size <= 5, "{size} bytes");
+        assert!(size * 2 < default_size, "{default_size} bytes by default");
+    }
+}
@@ -0,0 +1,111 @@
+//! Finding servers on the local network and measuring their ping.
+//!
+//! Clients send a [`DiscoveryRequest`] to [`DISCOVERY_PORT`], either broadcast
+//! on the LAN or directly to a known server, and servers answer with a
+//! [`DiscoveryResponse`] echoing the request's nonce.
+
+use std::{
+    io::{self, ErrorKind},
+    net::{SocketAddr, UdpSocket},
+};
+
+use bevy_replicon::bincode;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub const DISCOVERY_PORT: u16 = 8990;
+/// Every discovery packet starts with this, anything else is ignored
+const DISCOVERY_MAGIC: &[u8; 4] = b"PTR4";
+/// Requests are padded to this size, so that responses are never larger
+/// and the server can't be used to amplify traffic
+pub const DISCOVERY_REQUEST_LEN: usize = 128;
+/// Longest server or map name that fits into a response
+pub const MAX_SERVER_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DiscoveryRequest {
+    /// Echoed by the server to match responses with requests
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveryResponse {
+    pub nonce: u64,
+    pub info: ServerInfo,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    pub map: String,
+    pub players: u32,
+    pub max_clients: u32,
+    /// See [`crate::PROTOCOL_VERSION`]
+    pub protocol_version: u64,
+    /// Port of the game server itself
+    pub game_port: u16,
+}
+
+impl DiscoveryRequest {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = encode(DISCOVERY_MAGIC, self);
+        bytes.resize(DISCOVERY_REQUEST_LEN, 0);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < DISCOVERY_REQUEST_LEN {
+            return None;
+        }
+        decode(DISCOVERY_MAGIC, bytes)
+    }
+}
+
+impl DiscoveryResponse {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(DISCOVERY_MAGIC, self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        decode(DISCOVERY_MAGIC, bytes)
+    }
+}
+
+/// Cuts `name` down to [`MAX_SERVER_NAME_LEN`] bytes without splitting characters
+pub fn truncate_server_name(name: &str) -> String {
+    let mut end = name.len().min(MAX_SERVER_NAME_LEN);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    name[..end].to_owned()
+}
+
+/// Passes every datagram waiting on the non-blocking `socket` to `handle`.
+///
+/// Stops at the first error other than [`ErrorKind::WouldBlock`] and returns it,
+/// the caller reads again next tick. Some errors, like ICMP for an earlier reply,
+/// don't break the socket but can keep coming back, so reading on could never end.
+pub fn receive_datagrams(
+    socket: &UdpSocket,
+    buffer: &mut [u8],
+    mut handle: impl FnMut(&[u8], SocketAddr),
+) -> io::Result<()> {
+    loop {
+        match socket.recv_from(buffer) {
+            Ok((len, from)) => handle(&buffer[..len], from),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub(crate) fn encode(magic: &[u8; 4], message: &impl Serialize) -> Vec<u8> {
+    let mut bytes = magic.to_vec();
+    // serializing plain structs into a Vec can't fail
+    bincode::serialize_into(&mut bytes, message).unwrap();
+    bytes
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(magic: &[u8; 4], bytes: &[u8]) -> Option<T> {
+    let payload = bytes.strip_prefix(magic)?;
+    bincode::deserialize(payload).ok()
+}
@@ -1,36 +1,174 @@
+mod compact;
+pub mod discovery;
+pub mod registry;
+
 use bevy::prelude::*;
-use bevy_replicon::{prelude::*, renet::ClientId};
+use bevy_replicon::{
+    prelude::*,
+    renet::{transport::NETCODE_USER_DATA_BYTES, ClientId},
+    replicon_core::replication_rules::remove_component,
+};
 use serde::{Deserialize, Serialize};
 
 pub const PLAYER_HEIGHT: f32 = 1.0;
 
+/// Everything this close to a player's eyes is replicated to them, even behind walls
+pub const NEAR_RELEVANCE_RADIUS: f32 = 15.0;
+
 #[derive(Debug, Component, Serialize, Deserialize)]
 pub struct Player(pub ClientId);
 
 /// An intention to move in a particular direction.
+/// Collected on the client into an [`InputFrame`].
 #[derive(Event, Debug, Default, Deserialize, Serialize)]
 pub struct MoveDirection(pub Vec2);
 
 #[derive(Component, Serialize, Deserialize)]
 pub struct ReplicatedPos(pub GlobalTransform);
 
-/// Send from the client when it moves its eyes
+impl ReplicatedPos {
+    /// Whether `transform` is too close to the replicated one to be worth sending
+    pub fn is_close_to(&self, transform: &GlobalTransform) -> bool {
+        let (_, rotation, translation) = self.0.to_scale_rotation_translation();
+        let (_, new_rotation, new_translation) = transform.to_scale_rotation_translation();
+        translation.distance_squared(new_translation) < 0.001 * 0.001
+            && rotation.angle_between(new_rotation) < 0.001
+    }
+}
+
+/// Sent on the client when it moves its eyes.
+/// Collected into an [`InputFrame`].
 #[derive(Event, Serialize, Deserialize)]
 pub struct Aim(pub Direction3d);
 
+/// Player input of a single input frame, see [`INPUT_BATCHES_PER_SECOND`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputFrame {
+    /// Increases by one every frame, so the server can skip frames it has already seen
+    pub sequence: u32,
+    /// `None` if the player is not moving
+    pub movement: Option<Vec2>,
+    pub aim: Direction3d,
+}
+
+/// How many of the latest input frames are sent in every [`InputBatch`]
+pub const INPUT_REDUNDANCY: usize = 4;
+
+/// How many [`InputBatch`]es clients send per second, whatever their frame rate.
+/// The server's input limits are derived from it.
+pub const INPUT_BATCHES_PER_SECOND: f32 = 60.0;
+
+/// The latest input frames, oldest first.
+///
+/// Sent over an unreliable channel, every frame is repeated in several batches
+/// so a lost packet doesn't lose the input.
+#[derive(Event, Debug, Serialize, Deserialize)]
+pub struct InputBatch(pub Vec<InputFrame>);
+
+/// Where an entity that can aim is looking.
+/// Only added to entities that can aim, so props don't replicate it.
 #[derive(Component, Serialize, Deserialize)]
 pub struct ReplicatedAim(pub Direction3d);
 
+impl Default for ReplicatedAim {
+    fn default() -> Self {
+        // look forward by default
+        Self(Direction3d::NEG_Z)
+    }
+}
+
 #[derive(Component, Serialize, Deserialize)]
 pub struct Tint(pub Color);
 
+/// Marks players allowed to send [`AdminCommand`]s and use the free camera
+#[derive(Component, Debug, Serialize, Deserialize)]
+pub struct Admin;
+
+/// Per-player numbers shown on the scoreboard
+#[derive(Component, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PlayerStats {
+    /// Round trip time as measured by the server
+    pub ping_ms: u32,
+    /// How many times the player has fallen out of the world
+    pub deaths: u32,
+    /// How long the player has been on the server
+    pub connected_secs: u32,
+}
+
+/// Connection health of a player as seen from the server
+#[derive(Component, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct NetworkQuality {
+    pub rtt_ms: f32,
+    /// Fraction of lost packets, from 0 to 1
+    pub packet_loss: f32,
+    /// Bandwidth from the server to the player
+    pub sent_kbps: f32,
+    /// Bandwidth from the player to the server
+    pub received_kbps: f32,
+}
+
+impl NetworkQuality {
+    /// Round trip time above which the connection is considered degraded
+    pub const HIGH_RTT_MS: f32 = 200.0;
+    /// Packet loss above which the connection is considered degraded
+    pub const HIGH_PACKET_LOSS: f32 = 0.05;
+
+    pub fn is_degraded(&self) -> bool {
+        self.rtt_ms > Self::HIGH_RTT_MS || self.packet_loss > Self::HIGH_PACKET_LOSS
+    }
+}
+
+/// Longest player name the server will accept, in characters
+pub const MAX_NAME_LEN: usize = 24;
+
+/// Sent from the client to choose or change its name
 #[derive(Event, Debug, Serialize, Deserialize)]
 pub struct SetName(pub String);
 
-#[derive(Component, Debug, Serialize, Deserialize)]
+/// Sent from the server with the name it actually gave to the player,
+/// which may differ from the requested one
+#[derive(Event, Debug, Serialize, Deserialize)]
+pub struct NameAccepted(pub String);
+
+/// Longest chat message the server will relay, in characters
+pub const MAX_CHAT_MESSAGE_LEN: usize = 200;
+
+/// A line of chat typed by a player
+#[derive(Event, Debug, Serialize, Deserialize)]
+pub struct ChatMessage(pub String);
+
+/// A chat line relayed by the server to every client
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub struct ChatBroadcast {
+    pub sender: ClientId,
+    pub name: String,
+    pub tint: Color,
+    pub text: String,
+}
+
+#[derive(Component, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Appearance {
     Capsule,
     Box,
+    /// Path of a glTF model in the client's assets, standing in a player sized capsule.
+    /// Its animations named `Idle`, `Walk`, `Run` and `Fall` follow the [`Motion`].
+    Character(String),
+}
+
+/// How an entity moves, for the client to animate it
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Motion {
+    pub velocity: Vec3,
+    /// Standing on something rather than in the air
+    pub grounded: bool,
+}
+
+impl Motion {
+    /// Whether `other` looks the same as this when animated, so isn't worth sending
+    pub fn is_close_to(&self, other: &Motion) -> bool {
+        self.grounded == other.grounded
+            && self.velocity.distance_squared(other.velocity) < 0.1 * 0.1
+    }
 }
 
 #[derive(Bundle)]
@@ -38,7 +176,6 @@ pub struct ReplicationBundle {
     tint: Tint,
     appearance: Appearance,
     pos: ReplicatedPos,
-    aim: ReplicatedAim,
     replicate: Replication,
 }
 
@@ -48,8 +185,6 @@ impl ReplicationBundle {
             tint,
             appearance,
             pos: ReplicatedPos(default()),
-            // look forward by default
-            aim: ReplicatedAim(Direction3d::NEG_Z),
             replicate: Replication,
         }
     }
@@ -63,15 +198,32 @@ impl Plugin for PetriReplicationSetupPlugin {
             // components
             .replicate::<Player>()
             .replicate::<Tint>()
-            .replicate::<ReplicatedPos>()
-            .replicate::<ReplicatedAim>()
+            .replicate::<Admin>()
+            .replicate::<PlayerStats>()
+            .replicate::<NetworkQuality>()
+            .replicate_with::<ReplicatedPos>(
+                compact::serialize_pos,
+                compact::deserialize_pos,
+                remove_component::<ReplicatedPos>,
+            )
+            .replicate_with::<ReplicatedAim>(
+                compact::serialize_aim,
+                compact::deserialize_aim,
+                remove_component::<ReplicatedAim>,
+            )
             .replicate::<Appearance>()
+            .replicate::<Motion>()
             .replicate::<Name>()
             // events
             .add_client_event::<AdminCommand>(EventType::Ordered)
-            .add_client_event::<MoveDirection>(EventType::Ordered)
+            .add_client_event::<InputBatch>(EventType::Unreliable)
             .add_client_event::<SetName>(EventType::Ordered)
-            .add_client_event::<Aim>(EventType::Unordered)
+            .add_client_event::<ChatMessage>(EventType::Ordered)
+            // registered first so that clients of other versions can still read it
+            .add_server_event::<DisconnectNotice>(EventType::Ordered)
+            .add_server_event::<ChatBroadcast>(EventType::Ordered)
+            .add_server_event::<NameAccepted>(EventType::Ordered)
+            .add_server_event::<ServerShutdown>(EventType::Ordered)
     }
 }
 
@@ -86,7 +238,44 @@ pub fn get_player_capsule_size() -> (f32, f32) {
     (capsule_diameter, capsule_segment_half_height)
 }
 
+/// Bump when clients and servers of different versions can't play together
+pub const PROTOCOL_VERSION: u64 = 3;
+
+/// Connection user data that tells the server the client's [`PROTOCOL_VERSION`]
+pub fn protocol_user_data() -> [u8; NETCODE_USER_DATA_BYTES] {
+    let mut user_data = [0; NETCODE_USER_DATA_BYTES];
+    user_data[..8].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    user_data
+}
+
+/// Reads the client's protocol version from the connection user data
+pub fn protocol_version(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> u64 {
+    let mut version = [0; 8];
+    version.copy_from_slice(&user_data[..8]);
+    u64::from_le_bytes(version)
+}
+
+/// Sent to a client right before the server disconnects it, to tell why.
+///
+/// The format must not change, see [`PROTOCOL_VERSION`].
+#[derive(Event, Debug, Clone, Serialize, Deserialize)]
+pub enum DisconnectNotice {
+    Kicked(String),
+    VersionMismatch { server_version: u64 },
+}
+
+/// Sent every second while the server counts down to shutting down
+#[derive(Event, Debug, Serialize, Deserialize)]
+pub struct ServerShutdown {
+    pub seconds_left: u32,
+}
+
 #[derive(Debug, Event, Serialize, Deserialize)]
 pub enum AdminCommand {
-    SpawnBoxWall { side_size: u8, at: Vec3 },
+    SpawnBoxWall {
+        side_size: u8,
+        at: Vec3,
+    },
+    /// Despawns everything spawned by the sender's latest command
+    UndoLastSpawn,
 }
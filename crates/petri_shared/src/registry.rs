@@ -0,0 +1,82 @@
+//! Talking to `petri_registry`, the list of public servers.
+//!
+//! Servers send a [`RegistryRequest::Heartbeat`] every [`HEARTBEAT_INTERVAL`],
+//! and clients page through the list with [`RegistryRequest::List`].
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::{decode, encode, ServerInfo};
+
+pub const REGISTRY_PORT: u16 = 8991;
+const REGISTRY_MAGIC: &[u8; 4] = b"PTRR";
+/// Requests are padded to this size, so that a page of servers is never larger
+pub const REGISTRY_REQUEST_LEN: usize = 1024;
+pub const SERVERS_PER_PAGE: usize = 5;
+/// Longest public host a server can register with
+pub const MAX_HOST_LEN: usize = 64;
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Address of the registry, can be changed with the `PETRI_REGISTRY` environment variable
+pub fn registry_address() -> String {
+    std::env::var("PETRI_REGISTRY").unwrap_or_else(|_| format!("localhost:{REGISTRY_PORT}"))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RegistryRequest {
+    /// Adds or refreshes the sending server
+    Heartbeat {
+        info: ServerInfo,
+        /// Where clients should connect, the address the heartbeat came from if not set
+        public_host: Option<String>,
+    },
+    /// Removes the sending server, e.g. when it shuts down
+    Goodbye,
+    List {
+        /// Echoed by the registry to match responses with requests
+        nonce: u64,
+        page: u32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryResponse {
+    pub nonce: u64,
+    pub page: u32,
+    pub page_count: u32,
+    pub servers: Vec<RegisteredServer>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredServer {
+    pub host: String,
+    pub info: ServerInfo,
+}
+
+impl RegistryRequest {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = encode(REGISTRY_MAGIC, self);
+        if bytes.len() < REGISTRY_REQUEST_LEN {
+            bytes.resize(REGISTRY_REQUEST_LEN, 0);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < REGISTRY_REQUEST_LEN {
+            return None;
+        }
+        decode(REGISTRY_MAGIC, bytes)
+    }
+}
+
+impl RegistryResponse {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(REGISTRY_MAGIC, self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        decode(REGISTRY_MAGIC, bytes)
+    }
+}